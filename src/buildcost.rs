@@ -0,0 +1,21 @@
+//! Materials to construct each production building. Like the sink points,
+//! these are from the game rather than the recipe sheet.
+
+use crate::types::Ingredient;
+
+/// What it costs to build one `building`. None for buildings missing from
+/// the table.
+pub fn building_cost(building: &str) -> Option<Vec<Ingredient>> {
+    let cost: &[(&str, f64)] = match building {
+        "Smelter" => &[("Iron Rod", 5.0), ("Wire", 8.0)],
+        "Constructor" => &[("Reinforced Iron Plate", 2.0), ("Cable", 8.0)],
+        "Assembler" => &[("Reinforced Iron Plate", 8.0), ("Rotor", 4.0), ("Cable", 10.0)],
+        "Foundry" => &[("Modular Frame", 10.0), ("Rotor", 10.0), ("Concrete", 20.0)],
+        "Manufacturer" => &[("Motor", 5.0), ("Heavy Modular Frame", 10.0), ("Cable", 50.0), ("Plastic", 50.0)],
+        "Refinery" => &[("Motor", 10.0), ("Encased Industrial Beam", 10.0), ("Steel Pipe", 30.0), ("Copper Sheet", 20.0)],
+        "Packager" => &[("Steel Beam", 20.0), ("Rubber", 10.0), ("Plastic", 10.0)],
+        "Blender" => &[("Motor", 5.0), ("Heavy Modular Frame", 10.0), ("Aluminium Casing", 10.0), ("Radio Control Unit", 20.0)],
+        _ => return None,
+    };
+    Some(cost.iter().map(|&(part, quantity)| Ingredient { part: part.into(), quantity }).collect())
+}
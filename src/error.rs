@@ -0,0 +1,79 @@
+use std::fmt;
+use std::num::ParseFloatError;
+
+/// Errors from looking up and calculating recipes, and from importing them.
+/// Binaries wrap these in `anyhow`.
+#[derive(Debug)]
+pub enum SatisError {
+    /// Nothing matched the query. With `FindOpts::explain_no_match`, holds
+    /// the closest names and their edit distances.
+    RecipeNotFound { query: String, closest: Vec<(String, usize)> },
+    /// The best fuzzy matches were all below the minimum score, with the
+    /// top candidates and their scores
+    WeakMatch { query: String, candidates: Vec<(String, i64)> },
+    NoPreferedMultiple(String),
+    NoPowerUsage(String),
+    ClockTooLow,
+    ClockTooHigh,
+    InvalidCraftTime { recipe: String, craft_time: f64 },
+    MissingOutput(String),
+    InvalidQuantity(String),
+    InvalidConfig { path: String, reason: String },
+    ProductionCycle(Vec<String>),
+    TooManySomersloops { building: String, slots: u32 },
+    ShortRow { row: u64, n_fields: usize, expected: usize },
+    NotAnOutput { recipe: String, part: String },
+    ParseError(ParseFloatError),
+}
+
+impl fmt::Display for SatisError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SatisError::RecipeNotFound { query, closest } => {
+                write!(f, "Could not find recipe: {query}")?;
+                if !closest.is_empty() {
+                    write!(f, ". The closest are:")?;
+                }
+                for (name, distance) in closest {
+                    write!(f, "\n  {name} ({distance} edits)")?;
+                }
+                Ok(())
+            },
+            SatisError::WeakMatch { query, candidates } => {
+                write!(f, "No good match for recipe {query}, please be more specific. The closest are:")?;
+                for (name, score) in candidates {
+                    write!(f, "\n  {name} (score {score})")?;
+                }
+                Ok(())
+            },
+            SatisError::NoPreferedMultiple(building) => write!(f, "Please state a prefered number of machines for {building}"),
+            SatisError::NoPowerUsage(building) => write!(f, "Building {building} has no defined base power usage."),
+            SatisError::ClockTooLow => write!(f, "Clock speed must no be less than 0"),
+            SatisError::ClockTooHigh => write!(f, "Clock speed must no be more than 2.5"),
+            SatisError::InvalidCraftTime { recipe, craft_time } => write!(f, "Recipe {recipe} has a craft time of {craft_time}s, it must be more than 0"),
+            SatisError::MissingOutput(recipe) => write!(f, "Recipe {recipe} has no output"),
+            SatisError::InvalidQuantity(quantity) => write!(f, "Invalid quantity {quantity}"),
+            SatisError::InvalidConfig { path, reason } => write!(f, "Could not load config {path}: {reason}"),
+            SatisError::ProductionCycle(parts) => write!(f, "Production cycle: {}", parts.join(" -> ")),
+            SatisError::TooManySomersloops { building, slots } => write!(f, "{building} only has {slots} Somersloop slots"),
+            SatisError::ShortRow { row, n_fields, expected } => write!(f, "Row {row} has {n_fields} columns, expected {expected}"),
+            SatisError::NotAnOutput { recipe, part } => write!(f, "Recipe {recipe} does not make {part}"),
+            SatisError::ParseError(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl std::error::Error for SatisError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            SatisError::ParseError(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<ParseFloatError> for SatisError {
+    fn from(e: ParseFloatError) -> Self {
+        SatisError::ParseError(e)
+    }
+}
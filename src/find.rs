@@ -0,0 +1,195 @@
+//! Looking up recipes by name
+
+use fuzzy_matcher::FuzzyMatcher;
+use fuzzy_matcher::skim::SkimMatcherV2;
+
+use crate::error::SatisError;
+use crate::types::*;
+
+/// How recipe queries are matched against recipe names
+#[derive(Clone, Copy, Default)]
+pub struct FindOpts {
+    /// Also match against what unlocks each recipe
+    pub search_unlocks: bool,
+    /// Print the closest names to stderr when nothing matches
+    pub explain_no_match: bool,
+    /// Fuzzy scores below this are rejected, see [`min_score`]
+    pub min_score: Option<i64>,
+}
+
+/// Score a fuzzy match must reach to be trusted. A letter matched in place
+/// scores around 16, so this rejects queries whose letters are mostly
+/// scattered across the name.
+pub fn min_score(query: &str, find: FindOpts) -> i64 {
+    find.min_score.unwrap_or(10 * query.chars().filter(|c| !c.is_whitespace()).count() as i64)
+}
+
+/// Candidates scoring within this of the best fuzzy match are listed by
+/// [`find_recipes`], about one letter's worth
+const AMBIGUOUS_SCORE_DELTA: i64 = 8;
+
+/// Finds the recipe named `recipe`, ignoring case. Failing that, the best
+/// fuzzy match, where names starting with `recipe` win over ones that only
+/// contain its letters.
+pub fn find_recipe<'a>(all_recipes: &'a RecipeMap, recipe: &str, find: FindOpts) -> Result<&'a Recipe, SatisError> {
+    Ok(find_recipes(all_recipes, recipe, find)?[0])
+}
+
+/// Like [`find_recipe`], but returns every fuzzy match about as good as the
+/// best one, best first. An exact name match is returned alone.
+pub fn find_recipes<'a>(all_recipes: &'a RecipeMap, recipe: &str, find: FindOpts) -> Result<Vec<&'a Recipe>, SatisError> {
+    let query = recipe.to_lowercase();
+    if let Some(r) = all_recipes.values().find(|r| r.name.to_lowercase() == query) {
+        return Ok(vec![r]);
+    }
+    let matcher = SkimMatcherV2::default();
+    let mut fuzz: Vec<(&str, i64)> = all_recipes.iter()
+        .map(|(key, r)| {
+            let score = matcher.fuzzy_match(key, recipe);
+            let score = if find.search_unlocks {
+                score.max(matcher.fuzzy_match(&r.unlocks, recipe))
+            } else {
+                score
+            };
+            (key.as_str(), score)
+        })
+        .filter(|(_key, score)| score.is_some())
+        .map(|(key, score)| (key, score.expect("Filtered out Nones already")))
+        .collect();
+    // Ties go to the first name alphabetically, so the pick doesn't depend
+    // on the hash map's order
+    let is_prefix = |key: &str| key.to_lowercase().starts_with(&query);
+    fuzz.sort_by(|(a_key, a_score), (b_key, b_score)| {
+        is_prefix(b_key).cmp(&is_prefix(a_key))
+            .then(b_score.cmp(a_score))
+            .then(a_key.cmp(b_key))
+    });
+    let &(best_match_key, best_score) = fuzz.first().ok_or_else(|| SatisError::RecipeNotFound {
+        query: recipe.into(),
+        closest: if find.explain_no_match { closest_names(all_recipes, recipe) } else { Vec::new() },
+    })?;
+    // A name starting with the query is a good match whatever its score
+    if !is_prefix(best_match_key) && best_score < min_score(recipe, find) {
+        return Err(SatisError::WeakMatch {
+            query: recipe.into(),
+            candidates: fuzz.iter().take(3).map(|&(name, score)| (name.into(), score)).collect(),
+        });
+    }
+    fuzz.iter()
+        .take_while(|(key, score)| is_prefix(key) == is_prefix(best_match_key) && best_score - score <= AMBIGUOUS_SCORE_DELTA)
+        .map(|(key, _score)| all_recipes.get(*key).ok_or_else(|| SatisError::RecipeNotFound { query: (*key).into(), closest: Vec::new() }))
+        .collect()
+}
+
+/// The five names closest to `recipe`, with their edit distance. The fuzzy
+/// matcher needs every query character to appear in order, so it scores
+/// nothing for typos. Ranks names by edit distance instead.
+fn closest_names(all_recipes: &RecipeMap, recipe: &str) -> Vec<(String, usize)> {
+    let query: Vec<char> = recipe.to_lowercase().chars().collect();
+    let mut closest: Vec<(usize, &str)> = all_recipes.keys()
+        .map(|key| {
+            let name: Vec<char> = key.to_lowercase().chars().collect();
+            (edit_distance(&query, &name), key.as_str())
+        })
+        .collect();
+    closest.sort();
+    closest.into_iter().take(5).map(|(distance, name)| (name.into(), distance)).collect()
+}
+
+/// Levenshtein distance, the number of single character insertions,
+/// deletions and substitutions to turn `a` into `b`
+pub fn edit_distance(a: &[char], b: &[char]) -> usize {
+    let mut prev: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.iter().enumerate() {
+        let mut cur = vec![i + 1; b.len() + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitute = prev[j] + if ca == cb { 0 } else { 1 };
+            cur[j + 1] = substitute.min(prev[j + 1] + 1).min(cur[j] + 1);
+        }
+        prev = cur;
+    }
+    prev[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn recipe(name: &str, unlocks: &str) -> Recipe {
+        Recipe {
+            building: "Constructor".into(),
+            name: name.into(),
+            craft_time: 2.0,
+            is_alt: false,
+            unlocks: unlocks.into(),
+            is_unlocked: true,
+            in_1: None,
+            in_2: None,
+            in_3: None,
+            in_4: None,
+            out_1: Some(Ingredient { part: name.into(), quantity: 30.0 }),
+            out_2: None,
+            power_mw: None,
+        }
+    }
+
+    fn recipes(names: &[(&str, &str)]) -> RecipeMap {
+        names.iter().map(|&(name, unlocks)| (name.to_string(), recipe(name, unlocks))).collect()
+    }
+
+    fn ingots() -> RecipeMap {
+        recipes(&[("Iron Ingot", "0|1"), ("Pure Iron Ingot", "5|2"), ("Iron Alloy Ingot", "3|4")])
+    }
+
+    #[test]
+    fn exact_name_wins() {
+        assert_eq!(find_recipe(&ingots(), "iron ingot", FindOpts::default()).unwrap().name, "Iron Ingot");
+        assert_eq!(find_recipe(&ingots(), "Pure Iron Ingot", FindOpts::default()).unwrap().name, "Pure Iron Ingot");
+    }
+
+    #[test]
+    fn prefix_beats_fuzzy_match() {
+        assert_eq!(find_recipe(&ingots(), "iron ing", FindOpts::default()).unwrap().name, "Iron Ingot");
+        assert_eq!(find_recipe(&ingots(), "pure", FindOpts::default()).unwrap().name, "Pure Iron Ingot");
+    }
+
+    #[test]
+    fn weak_match_lists_candidates() {
+        let find = FindOpts { min_score: Some(1000), ..FindOpts::default() };
+        match find_recipe(&ingots(), "ingot", find) {
+            Err(SatisError::WeakMatch { query, candidates }) => {
+                assert_eq!(query, "ingot");
+                let mut names: Vec<&str> = candidates.iter().map(|(name, _)| name.as_str()).collect();
+                names.sort();
+                assert_eq!(names, ["Iron Alloy Ingot", "Iron Ingot", "Pure Iron Ingot"]);
+            },
+            other => panic!("Expected a weak match, got {:?}", other.map(|r| &r.name)),
+        }
+        assert!(find_recipe(&ingots(), "ingot", FindOpts::default()).is_ok());
+    }
+
+    #[test]
+    fn no_match_lists_closest_names() {
+        let find = FindOpts { explain_no_match: true, ..FindOpts::default() };
+        match find_recipe(&ingots(), "ion ingto", find) {
+            Err(SatisError::RecipeNotFound { query, closest }) => {
+                assert_eq!(query, "ion ingto");
+                assert_eq!(closest[0], ("Iron Ingot".to_string(), 3));
+                assert_eq!(closest.len(), 3);
+            },
+            other => panic!("Expected no match, got {:?}", other.map(|r| &r.name)),
+        }
+        assert!(matches!(
+            find_recipe(&ingots(), "ion ingto", FindOpts::default()),
+            Err(SatisError::RecipeNotFound { closest, .. }) if closest.is_empty()
+        ));
+    }
+
+    #[test]
+    fn unlocks_match_with_search_unlocks() {
+        let all = recipes(&[("Actual Snow", "F|5"), ("Iron Plate", "0|2")]);
+        let find = FindOpts { search_unlocks: true, ..FindOpts::default() };
+        assert_eq!(find_recipe(&all, "F|5", find).unwrap().name, "Actual Snow");
+        assert!(find_recipe(&all, "F|5", FindOpts::default()).is_err());
+    }
+}
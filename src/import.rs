@@ -1,40 +1,82 @@
+//! Imports recipe data.
+//! Uses the google sheets data from this project:
+//! https://steamcommunity.com/sharedfiles/filedetails/?id=2874178191
+//! (go to Production Recipes tab, then export as csv)
+
 use std::collections::HashMap;
+use std::io::Read;
+
+use anyhow::bail;
+use clap::Parser;
+
+use satis::error::SatisError;
+use satis::types::*;
 
-/// Imports recipe data.
-/// Uses the google sheets data from this project:
-/// https://steamcommunity.com/sharedfiles/filedetails/?id=2874178191
-/// (go to Production Recipes tab, then export as csv)
+#[derive(Parser)]
+struct Cli {
+    /// Fail on suspicious recipe data instead of printing a warning
+    #[arg(long)]
+    strict_import: bool,
+    /// Fail if a part looks like a fluid but isn't in the fluid list
+    #[arg(long)]
+    strict_fluids: bool,
+    /// Treat this part as a fluid too. Can be given several times.
+    #[arg(long)]
+    fluid: Vec<String>,
+}
 
-use anyhow;
+/// Words that show up in fluid names
+const FLUID_KEYWORDS: &[&str] = &["Acid", "Fuel", "Water", "Oil", "Solution", "Gas", "Residue", "Matter"];
 
-pub mod types;
-use types::*;
+/// Solid parts whose names contain a fluid keyword
+const NOT_FLUIDS: &[&str] = &[
+    "Dark Matter Crystal",
+    "Ficsonium Fuel Rod",
+    "Gas Filter",
+    "Gas Nobelisk",
+    "Plutonium Fuel Rod",
+    "Uranium Fuel Rod",
+];
 
 fn main() -> Result<(), anyhow::Error> {
-    let stdin = std::io::stdin();
-    {
-        // Skip two lines, as they are headers which we won't use
-        let mut input = String::new();
-        stdin.read_line(&mut input)?;
-        stdin.read_line(&mut input)?;
-    }
-    let mut reader = csv::ReaderBuilder::new()
-        .has_headers(false)
-        .from_reader(stdin);
+    let cli = Cli::parse();
+    assert!(register_fluids(&cli.fluid), "Fluids are only registered once, at startup");
+    let mut input = Vec::new();
+    std::io::stdin().read_to_end(&mut input)?;
+    let records = read_records(&input)?;
+    let (columns, skip) = find_columns(&records)?;
     let mut recipes = Vec::new();
-    for res in reader.records() {
-        let record = res?;
-        let recipe = parse_recipe(&record);
+    for (row, record) in &records[skip..] {
+        let row = *row;
+        let recipe = parse_recipe(record, &columns, row)
+            .and_then(|r| match r {
+                Some(r) => validate_recipe(&r, cli.strict_import).map(|_| Some(r)),
+                None => Ok(None),
+            });
         match recipe {
             Ok(Some(r)) => recipes.push(r),
             Ok(None) => continue,
             Err(e) => {
-                println!("Error at record {:?}", record);
-                return Err(e);
+                println!("Error at row {} record {:?}", row, record);
+                return Err(e.into());
             }
         }
     }
 
+    let n_no_power = recipes.iter()
+        .filter(|r| r.power_mw.is_none() && VARIABLE_POWER_BUILDINGS.contains(&r.building.as_str()))
+        .count();
+    if n_no_power > 0 {
+        eprintln!(
+            "Warning: {n_no_power} recipes in {} have no Power column value, so their power use is unknown",
+            VARIABLE_POWER_BUILDINGS.join(", "),
+        );
+    }
+
+    if cli.strict_fluids {
+        check_fluids(&recipes)?;
+    }
+
     // for r in recipes.iter() {
     //     println!("{:?}", r);
     // }
@@ -50,32 +92,355 @@ fn main() -> Result<(), anyhow::Error> {
     Ok(())
 }
 
-fn parse_recipe(record: &csv::StringRecord) -> Result<Option<Recipe>, anyhow::Error> {
+/// Reads every record in the csv, with the line it starts on, which is its
+/// row in the spreadsheet. The csv reader's own line count is off by one
+/// for CRLF files, so lines are counted here instead.
+fn read_records(input: &[u8]) -> Result<Vec<(u64, csv::StringRecord)>, csv::Error> {
+    let mut reader = csv::ReaderBuilder::new()
+        .has_headers(false)
+        // Lets the csv be annotated with lines starting with #
+        .comment(Some(b'#'))
+        // Short rows are checked in parse_recipe, with a better error
+        .flexible(true)
+        .from_reader(input);
+    reader.records()
+        .map(|record| {
+            let record = record?;
+            let start = record_start(input, record.position().map_or(0, |p| p.byte() as usize));
+            let row = input[..start].iter().filter(|&&b| b == b'\n').count() as u64 + 1;
+            Ok((row, record))
+        })
+        .collect()
+}
+
+/// Finds the columns from the header, and how many records it takes up.
+/// The sheet export starts with a line of column groups and a line of
+/// column names, but a single line of names works too. Without a header,
+/// falls back to the sheet's column order.
+fn find_columns(records: &[(u64, csv::StringRecord)]) -> Result<(Columns, usize), anyhow::Error> {
+    let is_header = |i: usize| records.get(i).is_some_and(|(_, r)| r.iter().any(|f| f == "Recipe Name"));
+    if is_header(0) {
+        Ok((Columns::from_header(&csv::StringRecord::new(), &records[0].1)?, 1))
+    } else if is_header(1) {
+        Ok((Columns::from_header(&records[0].1, &records[1].1)?, 2))
+    } else {
+        Ok((Columns::default(), 0))
+    }
+}
+
+/// The reader puts a record's position where the previous one ended, so
+/// skips the line endings, blank lines and comments in between
+fn record_start(input: &[u8], mut i: usize) -> usize {
+    loop {
+        match input.get(i) {
+            Some(b'\r' | b'\n') => i += 1,
+            Some(b'#') if i == 0 || input[i - 1] == b'\n' => {
+                i = input[i..].iter().position(|&b| b == b'\n').map_or(input.len(), |n| i + n + 1);
+            },
+            _ => return i,
+        }
+    }
+}
+
+/// Errors listing every part that looks like a fluid by name, but would
+/// be transported by belt
+fn check_fluids(recipes: &[Recipe]) -> Result<(), anyhow::Error> {
+    let mut suspects: Vec<&str> = recipes.iter()
+        .flat_map(|r| r.inputs().chain(r.outputs()))
+        .filter(|i| !i.is_fluid())
+        .map(|i| i.part.as_str())
+        .filter(|part| !part.ends_with("(Packaged)") && !NOT_FLUIDS.contains(part))
+        .filter(|part| FLUID_KEYWORDS.iter().any(|k| part.contains(k)))
+        .collect();
+    suspects.sort();
+    suspects.dedup();
+    if !suspects.is_empty() {
+        bail!("These parts look like fluids but are not in the fluid list: {}", suspects.join(", "));
+    }
+    Ok(())
+}
+
+/// Index of each field in a record, as (part, quantity) for ingredients
+struct Columns {
+    building: usize,
+    name: usize,
+    craft_time: usize,
+    is_alt: usize,
+    unlocks: usize,
+    is_unlocked: usize,
+    inputs: [(usize, usize); 4],
+    outputs: [(usize, usize); 2],
+    /// Optional average power use in MW, which the sheet doesn't have
+    power: Option<usize>,
+}
+
+impl Default for Columns {
+    /// The column order of the sheet export
+    fn default() -> Self {
+        Self {
+            building: 0,
+            name: 1,
+            craft_time: 2,
+            is_alt: 3,
+            unlocks: 4,
+            is_unlocked: 5,
+            inputs: [(6, 7), (8, 9), (10, 11), (12, 13)],
+            outputs: [(14, 15), (16, 17)],
+            power: None,
+        }
+    }
+}
+
+impl Columns {
+    /// Number of fields a record needs to have every column
+    fn width(&self) -> usize {
+        let ingredients = self.inputs.iter().chain(&self.outputs).flat_map(|&(p, q)| [p, q]);
+        [self.building, self.name, self.craft_time, self.is_alt, self.unlocks, self.is_unlocked]
+            .into_iter()
+            .chain(ingredients)
+            .chain(self.power)
+            .max()
+            .expect("There are columns")
+            + 1
+    }
+
+    /// Finds the columns by name. Ingredient columns share names like
+    /// "1" and "Qty 1", so they are told apart by the "Inputs" or
+    /// "Outputs" group they are under in the line above. Without a line of
+    /// groups, they need the full names, like "Inputs 1" and "Inputs Qty 1".
+    fn from_header(groups: &csv::StringRecord, names: &csv::StringRecord) -> Result<Columns, anyhow::Error> {
+        let mut index = HashMap::new();
+        let mut group = "";
+        for (i, name) in names.iter().enumerate() {
+            // Groups span several columns, but only the first is filled in
+            match groups.get(i) {
+                Some(g) if !g.is_empty() => group = g,
+                _ => (),
+            }
+            index.entry(name.to_string()).or_insert(i);
+            index.insert(format!("{group} {name}"), i);
+        }
+        let find = |name: &str| match index.get(name) {
+            Some(&i) => Ok(i),
+            None => Err(anyhow::anyhow!("Missing column {name} in csv header")),
+        };
+        let ingredient = |group: &str, n: usize| -> Result<(usize, usize), anyhow::Error> {
+            Ok((find(&format!("{group} {n}"))?, find(&format!("{group} Qty {n}"))?))
+        };
+        Ok(Columns {
+            building: find("Building")?,
+            name: find("Recipe Name")?,
+            craft_time: find("Craft Time")?,
+            is_alt: find("Alt. Recipe")?,
+            unlocks: find("Unlocks")?,
+            is_unlocked: find("Unlocked")?,
+            inputs: [
+                ingredient("Inputs", 1)?,
+                ingredient("Inputs", 2)?,
+                ingredient("Inputs", 3)?,
+                ingredient("Inputs", 4)?,
+            ],
+            outputs: [ingredient("Outputs", 1)?, ingredient("Outputs", 2)?],
+            power: index.get("Power").copied(),
+        })
+    }
+}
+
+fn parse_recipe(record: &csv::StringRecord, cols: &Columns, row: u64) -> Result<Option<Recipe>, SatisError> {
     let fields: Vec<&str> = record.iter().collect();
-    if fields[0].is_empty() { return Ok(None); }
+    // Rows without a building are leftovers below the recipes in the sheet
+    if fields.get(cols.building).is_none_or(|b| b.is_empty()) { return Ok(None); }
+    if fields.len() < cols.width() {
+        return Err(SatisError::ShortRow { row, n_fields: fields.len(), expected: cols.width() });
+    }
+    let name = fields[cols.name];
+    let craft_time: f64 = fields[cols.craft_time].parse()?;
+    if craft_time <= 0.0 {
+        return Err(SatisError::InvalidCraftTime { recipe: name.into(), craft_time });
+    }
+    if craft_time > 600.0 {
+        eprintln!("Warning: Recipe {name} has a suspiciously long craft time of {craft_time}s");
+    }
+    let ingredient = |(part, quantity): (usize, usize)| parse_ingredient(fields[part], fields[quantity]);
     Ok(Some(Recipe {
-        building: fields[0].into(),
-        name: fields[1].into(),
-        craft_time: fields[2].parse()?,
-        is_alt: fields[3] == "TRUE",
-        unlocks: fields[4].into(),
-        is_unlocked: fields[5] == "TRUE",
-        in_1: parse_ingredient(fields[6], fields[7])?,
-        in_2: parse_ingredient(fields[8], fields[9])?,
-        in_3: parse_ingredient(fields[10], fields[11])?,
-        in_4: parse_ingredient(fields[12], fields[13])?,
-        out_1: parse_ingredient(fields[14], fields[15])?,
-        out_2: parse_ingredient(fields[16], fields[17])?,
+        building: fields[cols.building].into(),
+        name: name.into(),
+        craft_time,
+        is_alt: fields[cols.is_alt] == "TRUE",
+        unlocks: fields[cols.unlocks].into(),
+        is_unlocked: fields[cols.is_unlocked] == "TRUE",
+        in_1: ingredient(cols.inputs[0])?,
+        in_2: ingredient(cols.inputs[1])?,
+        in_3: ingredient(cols.inputs[2])?,
+        in_4: ingredient(cols.inputs[3])?,
+        out_1: ingredient(cols.outputs[0])?,
+        out_2: ingredient(cols.outputs[1])?,
+        power_mw: match cols.power.map(|i| fields[i]) {
+            Some(power) if !power.is_empty() => Some(power.parse()?),
+            _ => None,
+        },
     }))
 }
 
-fn parse_ingredient(part: &str, quantity: &str) -> Result<Option<Ingredient>, anyhow::Error> {
+/// Checks a parsed recipe for data that would break later calculations.
+/// Problems are printed to stderr as warnings, or returned as errors when
+/// `strict` is set.
+fn validate_recipe(r: &Recipe, strict: bool) -> Result<(), SatisError> {
+    if r.out_1.is_none() {
+        let e = SatisError::MissingOutput(r.name.clone());
+        if strict { return Err(e); }
+        eprintln!("Warning: {e}");
+    }
+    // Quantities are per minute, but the game crafts whole amounts per
+    // cycle. Fluids come in any fraction of a cubic meter, like 1.5 Water.
+    for i in r.inputs().chain(r.outputs()).filter(|i| !i.is_fluid()) {
+        let per_craft = i.quantity / r.per_minute_factor();
+        if (per_craft - per_craft.round()).abs() > 0.01 {
+            eprintln!(
+                "Warning: Recipe {} has {}/min {}, which is {:.4} per {}s craft. Likely a data entry error.",
+                r.name, i.quantity, i.part, per_craft, r.craft_time,
+            );
+        }
+    }
+    Ok(())
+}
+
+fn parse_ingredient(part: &str, quantity: &str) -> Result<Option<Ingredient>, SatisError> {
     if part.is_empty() || quantity.is_empty() {
         return Ok(None);
     }
 
     Ok(Some(Ingredient{
         part: part.into(),
-        quantity: quantity.parse()?
+        quantity: parse_quantity(quantity)?,
     }))
 }
+
+/// Parses a decimal like "0.6667", or a fraction like "2/3"
+fn parse_quantity(quantity: &str) -> Result<f64, SatisError> {
+    match quantity.split_once('/') {
+        Some((num, den)) => {
+            let den: f64 = den.trim().parse()?;
+            if den == 0.0 {
+                return Err(SatisError::InvalidQuantity(quantity.into()));
+            }
+            Ok(num.trim().parse::<f64>()? / den)
+        },
+        None => Ok(quantity.parse()?),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const IRON_ROD: &str = "Constructor,Iron Rod,4,FALSE,0|3,TRUE,Iron Ingot,15,,,,,,,Iron Rod,15,,";
+
+    fn record(line: &str) -> csv::StringRecord {
+        csv::StringRecord::from(line.split(',').collect::<Vec<_>>())
+    }
+
+    #[test]
+    fn missing_output_fails_strict_validation() {
+        let r = parse_recipe(&record("Constructor,Iron Rod,4,FALSE,0|3,TRUE,Iron Ingot,15,,,,,,,,,,"), &Columns::default(), 1)
+            .unwrap()
+            .unwrap();
+        assert!(matches!(validate_recipe(&r, true), Err(SatisError::MissingOutput(name)) if name == "Iron Rod"));
+        assert!(validate_recipe(&r, false).is_ok());
+    }
+
+    #[test]
+    fn zero_craft_time_is_rejected() {
+        let e = parse_recipe(&record("Constructor,Iron Rod,0,FALSE,0|3,TRUE,Iron Ingot,15,,,,,,,Iron Rod,15,,"), &Columns::default(), 1);
+        assert!(matches!(e, Err(SatisError::InvalidCraftTime { recipe, .. }) if recipe == "Iron Rod"));
+    }
+
+    #[test]
+    fn complete_recipe_passes_strict_validation() {
+        let r = parse_recipe(&record(IRON_ROD), &Columns::default(), 1).unwrap().unwrap();
+        assert!(validate_recipe(&r, true).is_ok());
+    }
+
+    #[test]
+    fn comment_rows_are_skipped() {
+        let input = format!("# Checked against the game,,,\n{IRON_ROD}\n");
+        let records = read_records(input.as_bytes()).unwrap();
+        assert_eq!(records.len(), 1);
+        let (row, record) = &records[0];
+        assert_eq!(*row, 2);
+        assert_eq!(parse_recipe(record, &Columns::default(), *row).unwrap().unwrap().name, "Iron Rod");
+    }
+
+    const GROUPS: &str = "Building Information,,,Unlock Information,,,Inputs,,,,,,,,Outputs,,,";
+    const NAMES: &str = "Building,Recipe Name,Craft Time,Alt. Recipe,Unlocks,Unlocked,1,Qty 1,2,Qty 2,3,Qty 3,4,Qty 4,1,Qty 1,2,Qty 2";
+
+    /// Moves every field of `line` from index i to order[i]
+    fn shuffle(line: &str, order: &[usize]) -> String {
+        let fields: Vec<&str> = line.split(',').collect();
+        let mut shuffled = vec![""; fields.len()];
+        for (i, f) in fields.iter().enumerate() {
+            shuffled[order[i]] = f;
+        }
+        shuffled.join(",")
+    }
+
+    fn parse_csv(input: &str) -> Recipe {
+        let records = read_records(input.as_bytes()).unwrap();
+        let (columns, skip) = find_columns(&records).unwrap();
+        let (row, record) = &records[skip];
+        parse_recipe(record, &columns, *row).unwrap().unwrap()
+    }
+
+    fn assert_iron_rod(r: &Recipe) {
+        assert_eq!(r.building, "Constructor");
+        assert_eq!(r.name, "Iron Rod");
+        assert_eq!(r.craft_time, 4.0);
+        assert_eq!(r.unlocks, "0|3");
+        assert_eq!(r.in_1.as_ref().map(|i| (i.part.as_str(), i.quantity)), Some(("Iron Ingot", 15.0)));
+        assert_eq!(r.out_1.as_ref().map(|i| (i.part.as_str(), i.quantity)), Some(("Iron Rod", 15.0)));
+        assert!(r.in_2.is_none() && r.out_2.is_none());
+    }
+
+    #[test]
+    fn shuffled_columns_are_found_by_header() {
+        // Outputs first, then inputs, then the recipe details reversed
+        let order = [17, 16, 15, 14, 13, 12, 4, 5, 6, 7, 8, 9, 10, 11, 0, 1, 2, 3];
+        // The group names sit over the first column of each group
+        let groups = "Outputs,,,,Inputs,,,,,,,,Unlock Information,,,Building Information,,";
+        let input = format!("{groups}\n{}\n{}\n", shuffle(NAMES, &order), shuffle(IRON_ROD, &order));
+        assert_iron_rod(&parse_csv(&input));
+    }
+
+    #[test]
+    fn sheet_header_is_skipped() {
+        assert_iron_rod(&parse_csv(&format!("{GROUPS}\n{NAMES}\n{IRON_ROD}\n")));
+    }
+
+    #[test]
+    fn single_header_row_is_found() {
+        let names = "Building,Recipe Name,Craft Time,Alt. Recipe,Unlocks,Unlocked,\
+            Inputs 1,Inputs Qty 1,Inputs 2,Inputs Qty 2,Inputs 3,Inputs Qty 3,Inputs 4,Inputs Qty 4,\
+            Outputs 1,Outputs Qty 1,Outputs 2,Outputs Qty 2";
+        assert_iron_rod(&parse_csv(&format!("{names}\n{IRON_ROD}\n")));
+    }
+
+    #[test]
+    fn no_header_is_positional() {
+        assert_iron_rod(&parse_csv(&format!("{IRON_ROD}\n")));
+    }
+
+    #[test]
+    fn quantities_parse_as_fractions() {
+        assert!((parse_quantity("2/3").unwrap() - 2.0 / 3.0).abs() < 1e-12);
+        assert_eq!(parse_quantity(" 15 / 2 ").unwrap(), 7.5);
+        assert_eq!(parse_quantity("37.5").unwrap(), 37.5);
+        assert!(matches!(parse_quantity("1/0"), Err(SatisError::InvalidQuantity(q)) if q == "1/0"));
+    }
+
+    #[test]
+    fn rows_count_crlf_lines() {
+        let input = b"a,b\r\n\r\nc,d\r\n# note\r\ne,f\r\n";
+        let rows: Vec<u64> = read_records(input).unwrap().iter().map(|(row, _)| *row).collect();
+        assert_eq!(rows, vec![1, 3, 5]);
+    }
+}
@@ -0,0 +1,117 @@
+//! Minimal JSON rendering for the types we print. Each value is rendered
+//! on a single line, so it also works for line delimited JSON.
+
+use crate::types::*;
+
+pub trait ToJson {
+    fn to_json(&self) -> String;
+}
+
+impl ToJson for Ingredient {
+    fn to_json(&self) -> String {
+        let transport = match self.transport() {
+            Transport::Belt => "Belt",
+            Transport::Pipe => "Pipe",
+        };
+        format!(
+            "{{\"part\":{},\"quantity\":{},\"transport\":{}}}",
+            string(&self.part),
+            number(self.quantity),
+            string(transport),
+        )
+    }
+}
+
+impl<T: ToJson> ToJson for Option<T> {
+    fn to_json(&self) -> String {
+        match self {
+            Some(v) => v.to_json(),
+            None => "null".into(),
+        }
+    }
+}
+
+impl ToJson for Recipe {
+    fn to_json(&self) -> String {
+        format!(
+            "{{\"building\":{},\"name\":{},\"craft_time\":{},\"is_alt\":{},\"unlocks\":{},\"is_unlocked\":{},\"in_1\":{},\"in_2\":{},\"in_3\":{},\"in_4\":{},\"out_1\":{},\"out_2\":{},\"power_mw\":{}}}",
+            string(&self.building),
+            string(&self.name),
+            number(self.craft_time),
+            self.is_alt,
+            string(&self.unlocks),
+            self.is_unlocked,
+            self.in_1.to_json(),
+            self.in_2.to_json(),
+            self.in_3.to_json(),
+            self.in_4.to_json(),
+            self.out_1.to_json(),
+            self.out_2.to_json(),
+            self.power_mw.map(number).unwrap_or_else(|| "null".into()),
+        )
+    }
+}
+
+impl ToJson for RecipeCalc {
+    fn to_json(&self) -> String {
+        format!(
+            "{{\"use_belt\":{},\"use_pipe\":{},\"m_per_belt\":{},\"m_per_pipe\":{},\"n_boxes\":{},\"pref_mult\":{},\"clock\":{},\"power_usage_mw\":{},\"transport_use\":{},\"amplification\":{},\"below_min_clock\":{}}}",
+            self.use_belt,
+            self.use_pipe,
+            number(self.m_per_belt),
+            number(self.m_per_pipe),
+            number(self.n_boxes),
+            number(self.pref_mult),
+            number(self.clock),
+            number(self.power_usage_mw),
+            number(self.transport_use),
+            number(self.amplification),
+            self.below_min_clock,
+        )
+    }
+}
+
+impl ToJson for TreeNode<'_> {
+    fn to_json(&self) -> String {
+        format!(
+            "{{\"part\":{},\"rate\":{},\"recipe\":{},\"machines\":{},\"inputs\":{}}}",
+            string(&self.part),
+            number(self.rate),
+            self.recipe.map(|r| string(&r.name)).unwrap_or_else(|| "null".into()),
+            number(self.machines),
+            array(self.inputs.iter().map(|i| i.to_json())),
+        )
+    }
+}
+
+/// Joins already rendered JSON values into an array
+pub fn array(values: impl Iterator<Item = String>) -> String {
+    format!("[{}]", values.collect::<Vec<_>>().join(","))
+}
+
+pub fn string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// JSON has no NaN or infinity, those become null
+pub fn number(n: f64) -> String {
+    if n.is_finite() {
+        format!("{n}")
+    } else {
+        "null".into()
+    }
+}
@@ -0,0 +1,96 @@
+//! Recipe lookup and blueprint math for Satisfactory, used by the `satis`
+//! and `import` binaries.
+//!
+//! The stable entry points are:
+//! - `get_all_recipes` and `read_recipes` to load the recipe data
+//! - `find::find_recipe` to look up a recipe by name
+//! - `types::State`, whose defaults can be read from a `types::Config` file
+//! - `types::register_fluids` to transport more parts by pipe
+//! - `types::Recipe::calc` to size a blueprint, and
+//!   `types::Recipe::buildings_for_rate` for an exact rate
+//! - `types::production_tree` and `types::best_producer` to expand inputs
+//! - `buildcost::building_cost` for the materials to build a machine
+//!
+//! The printers in `output` and `json` format reports for the CLI, and may
+//! change with it.
+
+use std::path::Path;
+
+use anyhow::Context;
+
+pub mod buildcost;
+pub mod error;
+pub mod find;
+pub mod json;
+pub mod output;
+pub mod sink;
+pub mod types;
+use types::*;
+
+/// Reads the recipes in `recipes`, then those in `custom`, which replace
+/// recipes with the same name
+pub fn get_all_recipes(recipes: &Path, custom: Option<&Path>) -> Result<RecipeMap, anyhow::Error> {
+    let mut all_recipes = read_recipes(recipes)?;
+    if let Some(custom) = custom {
+        all_recipes.extend(read_recipes(custom)?);
+    }
+    Ok(all_recipes)
+}
+
+pub fn read_recipes(path: &Path) -> Result<RecipeMap, anyhow::Error> {
+    let recipes = std::fs::read_to_string(path)
+        .with_context(|| format!("Could not read recipes from {}", path.display()))?;
+    let recipes = toml::from_str::<Recipes>(&recipes)
+        .with_context(|| format!("Could not parse recipes in {}", path.display()))?;
+    Ok(recipes.recipes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn iron_rod_toml(craft_time: f64, in_quantity: f64) -> String {
+        format!(
+            "[recipes.\"Iron Rod\"]
+building = \"Constructor\"
+name = \"Iron Rod\"
+craft_time = {craft_time:?}
+is_alt = false
+unlocks = \"0|3\"
+is_unlocked = true
+in_1 = {{ part = \"Iron Ingot\", quantity = {in_quantity:?} }}
+out_1 = {{ part = \"Iron Rod\", quantity = 15.0 }}
+"
+        )
+    }
+
+    #[test]
+    fn custom_recipe_replaces_bundled_one() {
+        let dir = std::env::temp_dir();
+        let bundled = dir.join(format!("satis-bundled-{}.toml", std::process::id()));
+        let custom = dir.join(format!("satis-custom-{}.toml", std::process::id()));
+        let plastic = "[recipes.Plastic]
+building = \"Refinery\"
+name = \"Plastic\"
+craft_time = 6.0
+is_alt = false
+unlocks = \"5|2\"
+is_unlocked = true
+in_1 = { part = \"Crude Oil\", quantity = 30.0 }
+out_1 = { part = \"Plastic\", quantity = 20.0 }
+";
+        std::fs::write(&bundled, iron_rod_toml(4.0, 15.0) + plastic).unwrap();
+        std::fs::write(&custom, iron_rod_toml(2.0, 10.0)).unwrap();
+        let all_recipes = get_all_recipes(&bundled, Some(&custom));
+        std::fs::remove_file(&bundled).unwrap();
+        std::fs::remove_file(&custom).unwrap();
+        let all_recipes = all_recipes.unwrap();
+
+        assert_eq!(all_recipes.len(), 2);
+        let iron_rod = &all_recipes["Iron Rod"];
+        assert_eq!(iron_rod.craft_time, 2.0);
+        assert_eq!(iron_rod.in_1.as_ref().unwrap().quantity, 10.0);
+        // Recipes the custom file leaves out are kept
+        assert_eq!(all_recipes["Plastic"].craft_time, 6.0);
+    }
+}
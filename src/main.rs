@@ -1,143 +1,856 @@
-use anyhow::anyhow;
-use clap::{Parser, Subcommand};
-use fuzzy_matcher::FuzzyMatcher;
-use fuzzy_matcher::skim::SkimMatcherV2;
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
 
-pub mod types;
-use types::*;
+use anyhow::{bail, Context};
+use clap::{Args, Parser, Subcommand};
+use serde::Deserialize;
+
+use satis::buildcost::building_cost;
+use satis::error::SatisError;
+use satis::find::{find_recipe, find_recipes, FindOpts};
+use satis::json::{self, ToJson};
+use satis::output::{print_packaging_hints, print_recipe_graph, print_tree, Format, OutputConfig};
+use satis::sink::sink_points;
+use satis::types::*;
+use satis::get_all_recipes;
 
 #[derive(Parser)]
 struct Cli {
     #[command(subcommand)]
     command: Command,
+    /// Recipes to load instead of ./all_recipes.toml, as written by import
+    #[arg(long = "recipes", global = true, default_value = "./all_recipes.toml")]
+    recipe_file: PathBuf,
+    /// Extra recipes to load, in the same format as all_recipes.toml.
+    /// Recipes with the same name replace the built in ones.
+    #[arg(long, global = true)]
+    custom: Option<PathBuf>,
+    /// TOML file with belt and pipe speeds and prefered machine multiples.
+    /// Fields it leaves out keep their default.
+    #[arg(long, global = true)]
+    config: Option<PathBuf>,
+    /// Belt tier to plan for, instead of the config's belt speed
+    #[arg(long, global = true, value_enum)]
+    belt: Option<BeltTier>,
+    /// Pipe tier to plan for, instead of the config's pipe speed
+    #[arg(long, global = true, value_enum)]
+    pipe: Option<PipeTier>,
+    /// Leave out recipes that are not unlocked, before any lookup
+    #[arg(long, global = true)]
+    unlocked_only: bool,
+    /// Transport this part by pipe, for fluids missing from the built in
+    /// list. Can be given several times.
+    #[arg(long, global = true)]
+    fluid: Vec<String>,
+    /// Lowest clock to plan blueprints for, as a fraction (0.5 is 50%)
+    #[arg(long, global = true)]
+    min_clock: Option<f64>,
+    /// Highest clock to plan blueprints for, up to 2.5 with power shards
+    #[arg(long, global = true)]
+    max_clock: Option<f64>,
+    /// Only plan whole boxes of machines at 100% clock, never underclock
+    #[arg(long, global = true)]
+    whole_only: bool,
+    /// Also show per minute quantities per second
+    #[arg(long, global = true)]
+    per_sec: bool,
+    /// Show each recipe's quantities per craft instead of per minute
+    #[arg(long, global = true, conflicts_with = "per_sec")]
+    per_cycle: bool,
+    /// Number of decimals to show for all quantities
+    #[arg(long, global = true)]
+    precision: Option<usize>,
+    /// Show quantities like 0.6667 as fractions like 2/3
+    #[arg(long, global = true)]
+    fractions: bool,
+    /// TOML file with a list `out_2 = [..]` of recipes whose second output
+    /// is their main product, used when picking producers
+    #[arg(long, global = true)]
+    primary_outputs: Option<PathBuf>,
+    /// When a recipe query matches nothing, list the closest recipe names
+    #[arg(long, global = true)]
+    explain_no_match: bool,
+    /// Lowest fuzzy match score to accept for a recipe query, by default 10
+    /// per letter in the query
+    #[arg(long, global = true)]
+    min_score: Option<i64>,
+    /// Write reports as aligned text or as JSON
+    #[arg(long, global = true, value_enum, default_value_t = Format::Text)]
+    format: Format,
+    /// Write the report to this file instead of stdout
+    #[arg(long, global = true)]
+    output_file: Option<PathBuf>,
 }
 
 #[derive(Subcommand)]
 enum Command {
-    Calc{recipe: String},
+    Calc{
+        #[arg(required = true)]
+        recipes: Vec<String>,
+        #[command(flatten)]
+        opts: CalcOpts,
+    },
+    /// List recipes whose name matches a wildcard pattern (`*` and `?`)
+    List{
+        pattern: String,
+        #[command(flatten)]
+        opts: ListOpts,
+    },
+    /// Convert a rate between per-min, per-sec, belts and pipes
+    Convert{amount: f64, from: String, to: String},
+    /// Check that the building tables agree with each other and the recipes
+    Verify,
+    /// Expand a recipe into the recipes making its inputs, down to raw
+    /// resources, for `amount` per minute of its output
+    Tree{recipe: String, amount: f64},
+    /// Print only the raw resources a Tree would use, summed per part
+    Raw{recipe: String, amount: f64},
+    /// Print the machines a Tree would use, and the materials to build them
+    BuildCost{recipe: String, amount: f64},
+    /// Print the fewest machines that make exactly `amount` per minute
+    Rate{
+        recipe: String,
+        amount: f64,
+        /// Output to count, instead of the primary output
+        #[arg(long)]
+        part: Option<String>,
+    },
+    /// Compare every recipe making a part, most output per MW first
+    Compare{part: String},
+    /// Print the AWESOME Sink points per minute of one machine's outputs
+    Points{recipe: String},
+    /// Sum the power use of the suggested blueprints for several recipes
+    PowerTotal{
+        #[arg(required = true)]
+        recipes: Vec<String>,
+    },
+    /// Print the dependency graph of all recipes in Graphviz DOT format
+    Graph{
+        /// Leave out recipes that are not unlocked
+        #[arg(long)]
+        unlocked: bool,
+        /// Print each recipe in the graph as a line of JSON instead of DOT
+        #[arg(long)]
+        json_lines: bool,
+    },
+}
+
+#[derive(Args)]
+struct ListOpts {
+    /// Only list recipes that use or produce fluids
+    #[arg(long)]
+    fluids: bool,
+    /// Only list recipes made in buildings of this category
+    #[arg(long, value_enum)]
+    category: Option<BuildingCategory>,
+    /// Leave out alternate recipes
+    #[arg(long)]
+    no_alt: bool,
+    /// Print each recipe as a line of JSON
+    #[arg(long)]
+    json_lines: bool,
+    /// Print only the number of matching recipes
+    #[arg(long, conflicts_with = "json_lines")]
+    count: bool,
+}
+
+#[derive(Args)]
+struct CalcOpts {
+    /// Print why each ingredient is transported by belt or pipe
+    #[arg(long)]
+    explain_transport: bool,
+    /// Print the belts needed per output, and whether outputs can share a belt
+    #[arg(long)]
+    outputs_detail: bool,
+    /// Print the recipe picked to make each input, for one machine
+    #[arg(long)]
+    chain: bool,
+    /// Print whether a blueprint's machines fit in a Blueprint Designer
+    #[arg(long)]
+    layout: bool,
+    /// Print the total belts and pipes needed by all recipes at the end
+    #[arg(long)]
+    transport_summary: bool,
+    /// Also match recipe queries against what unlocks the recipe
+    #[arg(long)]
+    search_unlocks: bool,
+    /// Print the recipe's raw data instead of the blueprint calculation
+    #[arg(long)]
+    raw: bool,
+    /// Print a single summary line per recipe
+    #[arg(long)]
+    oneline: bool,
+    /// Somersloops to put in each machine to amplify its output
+    #[arg(long, default_value_t = 0)]
+    sloops: u32,
+    /// Print every recipe matching about as well as the best one
+    #[arg(long)]
+    all: bool,
 }
 
 fn main() -> Result<(), anyhow::Error> {
+    let cli = Cli::parse();
+
     // println!("Reading recipes");
-    let all_recipes = std::fs::read_to_string("./all_recipes.toml")?;
-    let all_recipes = toml::from_str::<Recipes>(&all_recipes)?.recipes;
+    let mut all_recipes = get_all_recipes(&cli.recipe_file, cli.custom.as_deref())?;
+    if cli.unlocked_only {
+        all_recipes.retain(|_, r| r.is_unlocked);
+    }
 
-    let state = State::default();
+    let mut out: Box<dyn Write> = match &cli.output_file {
+        Some(path) => {
+            let file = File::create(path)
+                .with_context(|| format!("Could not create output file {}", path.display()))?;
+            Box::new(BufWriter::new(file))
+        },
+        None => Box::new(std::io::stdout().lock()),
+    };
+
+    let cfg = OutputConfig {
+        format: cli.format,
+        per_sec: cli.per_sec,
+        precision: cli.precision,
+        fractions: cli.fractions,
+        per_cycle: cli.per_cycle,
+    };
+    let find = FindOpts {
+        explain_no_match: cli.explain_no_match,
+        min_score: cli.min_score,
+        ..FindOpts::default()
+    };
+
+    // Which parts are fluids matters to every command, but a config that
+    // fails to load is only an error for the commands that build State
+    let config = read_config(&cli);
+    register_cli_fluids(&cli, &config);
 
-    let cli = Cli::parse();
     match &cli.command {
-        Command::Calc{recipe} => calc(state, all_recipes, recipe.as_str())?,
+        Command::Calc{recipes, opts} => calc(&mut out, &cfg, load_state(&cli, config)?, all_recipes, recipes, find, opts)?,
+        Command::List{pattern, opts} => list(&mut out, &cfg, &all_recipes, pattern.as_str(), opts)?,
+        Command::Convert{amount, from, to} => convert(&mut out, &cfg, &load_state(&cli, config)?, *amount, from, to)?,
+        Command::Tree{recipe, amount} => {
+            let state = load_state(&cli, config)?;
+            let r = find_recipe(&all_recipes, recipe, find)?;
+            let tree = production_tree(&all_recipes, r, *amount, &state)?;
+            match cfg.format {
+                Format::Text => print_tree(&mut out, &cfg, &tree, 0)?,
+                Format::Json => writeln!(out, "{}", tree.to_json())?,
+            }
+        },
+        Command::Raw{recipe, amount} => {
+            let state = load_state(&cli, config)?;
+            let r = find_recipe(&all_recipes, recipe, find)?;
+            let raw = production_tree(&all_recipes, r, *amount, &state)?.raw_resources();
+            match cfg.format {
+                Format::Text => for i in &raw {
+                    writeln!(out, "{:24} {:10.p$}/min", i.part, i.quantity, p = cfg.prec(2))?;
+                },
+                Format::Json => writeln!(out, "{}", json::array(raw.iter().map(|i| i.to_json())))?,
+            }
+        },
+        Command::BuildCost{recipe, amount} => {
+            let state = load_state(&cli, config)?;
+            let r = find_recipe(&all_recipes, recipe, find)?;
+            let tree = production_tree(&all_recipes, r, *amount, &state)?;
+            build_cost(&mut out, &cfg, &tree)?;
+        },
+        Command::Rate{recipe, amount, part} => {
+            let state = load_state(&cli, config)?;
+            let r = find_recipe(&all_recipes, recipe, find)?;
+            let part = match part {
+                Some(p) => p.as_str(),
+                None => &r.primary_output(&state).ok_or_else(|| SatisError::MissingOutput(r.name.clone()))?.part,
+            };
+            let (n_machines, clock) = r.buildings_for_rate(&state, *amount, part)?;
+            match cfg.format {
+                Format::Text => writeln!(
+                    out,
+                    "{} x {} at {:.p$}% make {} {}/min",
+                    n_machines, r.building, clock * 100.0, amount, part, p = cfg.prec(4),
+                )?,
+                Format::Json => writeln!(
+                    out,
+                    "{{\"recipe\":{},\"part\":{},\"machines\":{},\"clock\":{}}}",
+                    json::string(&r.name), json::string(part), n_machines, json::number(clock),
+                )?,
+            }
+        },
+        Command::Compare{part} => compare(&mut out, &cfg, &load_state(&cli, config)?, &all_recipes, part)?,
+        Command::Points{recipe} => points(&mut out, &cfg, &all_recipes, recipe, find)?,
+        Command::PowerTotal{recipes} => power_total(&mut out, &cfg, &load_state(&cli, config)?, &all_recipes, recipes, find)?,
+        Command::Verify => verify(&mut out, &cfg, &load_state(&cli, config)?, &all_recipes)?,
+        Command::Graph{unlocked, json_lines} if *json_lines || cfg.format == Format::Json => {
+            let mut recipes: Vec<&Recipe> = all_recipes.values()
+                .filter(|r| !unlocked || r.is_unlocked)
+                .collect();
+            recipes.sort_by(|a, b| a.name.cmp(&b.name));
+            if *json_lines {
+                print_json_lines(&mut out, &recipes)?;
+            } else {
+                writeln!(out, "{}", json::array(recipes.iter().map(|r| r.to_json())))?;
+            }
+        },
+        Command::Graph{unlocked, ..} => print_recipe_graph(&mut out, &all_recipes, *unlocked)?,
     }
 
+    out.flush()?;
     Ok(())
 }
 
-fn find_recipe<'a, 'b>(all_recipes: &'a RecipeMap, recipe: &'b str) -> Result<&'a Recipe, anyhow::Error> {
-    let matcher = SkimMatcherV2::default();
-    let mut fuzz: Vec<(&str, i64)> = all_recipes.keys()
-        .map(String::as_str)
-        .map(|key| (key, matcher.fuzzy_match(key, recipe)))
-        .filter(|(_key, score)| score.is_some())
-        .map(|(key, score)| (key, score.expect("Filtered out Nones already")))
-        .collect();
-    fuzz.sort_by_key(|(_key, score)| *score);
-    let best_match_key = fuzz.last().ok_or(anyhow!("Could not find recipe: {recipe}"))?.0;
-    all_recipes.get(best_match_key).ok_or(anyhow!("Could not find recipe: {best_match_key}"))
+/// Reads the config file, or without one the defaults for the chosen belt
+/// and pipe tiers
+fn read_config(cli: &Cli) -> Result<Config, SatisError> {
+    match &cli.config {
+        Some(path) => Config::from_file(path),
+        None => Ok(Config {
+            state: State::tier_preset(cli.belt.unwrap_or_default(), cli.pipe.unwrap_or_default()),
+            extra_fluids: Vec::new(),
+        }),
+    }
 }
 
-fn calc(state: State, all_recipes: RecipeMap, recipe: &str) -> Result<(), anyhow::Error> {
-    let r = find_recipe(&all_recipes, recipe)?;
-    r.print_calc(&state)?;
-    Ok(())
+/// Registers the fluids from --fluid and from the config, if it loaded
+fn register_cli_fluids(cli: &Cli, config: &Result<Config, SatisError>) {
+    let mut fluids = cli.fluid.clone();
+    if let Ok(config) = config {
+        fluids.extend(config.extra_fluids.iter().cloned());
+    }
+    assert!(register_fluids(&fluids), "Fluids are only registered once, at startup");
+}
+
+/// Builds the State for commands that need it from the config and flags.
+/// Commands which only look up recipe data don't call this.
+fn load_state(cli: &Cli, config: Result<Config, SatisError>) -> Result<State, anyhow::Error> {
+    let mut state = config?.state;
+    // Tiers given as flags still win over the config's speeds
+    if let Some(belt) = cli.belt {
+        state.belt_ipm = belt.ipm();
+    }
+    if let Some(pipe) = cli.pipe {
+        state.pipe_ipm = pipe.ipm();
+    }
+    if let Some(min_clock) = cli.min_clock {
+        if !(0.0..=2.5).contains(&min_clock) {
+            bail!("--min-clock must be between 0 and 2.5, got {min_clock}");
+        }
+        state.min_clock = min_clock;
+    }
+    if let Some(max_clock) = cli.max_clock {
+        if !(1.0..=2.5).contains(&max_clock) {
+            bail!("--max-clock must be between 1.0 and 2.5, got {max_clock}");
+        }
+        state.max_clock = max_clock;
+    }
+    if state.min_clock > state.max_clock {
+        bail!("The min clock {} is above the max clock {}", state.min_clock, state.max_clock);
+    }
+    state.whole_only = cli.whole_only;
+    if let Some(path) = &cli.primary_outputs {
+        state.out_2_primary = read_primary_outputs(path)?;
+    }
+    Ok(state)
+}
+
+#[derive(Deserialize)]
+struct PrimaryOutputs {
+    out_2: HashSet<String>,
+}
+
+fn read_primary_outputs(path: &Path) -> Result<HashSet<String>, anyhow::Error> {
+    let primary = std::fs::read_to_string(path)
+        .with_context(|| format!("Could not read primary outputs from {}", path.display()))?;
+    let primary = toml::from_str::<PrimaryOutputs>(&primary)
+        .with_context(|| format!("Could not parse primary outputs in {}", path.display()))?;
+    Ok(primary.out_2)
 }
 
-impl Recipe {
-    pub fn print_calc(&self, state: &State) -> anyhow::Result<()> {
-        let (max_belt, max_pipe) = self.max_outputs();
-        let RecipeCalc {
-            use_belt,
-            use_pipe,
-            m_per_belt,
-            m_per_pipe,
-            n_boxes,
-            pref_mult,
-            clock,
-            power_usage_mw,
-        } = self.calc(state)?;
-
-        println!("\n{:12}{:>39}", self.building, self.name);
-        println!("\n  --  IN  --");
-        print_ingredient(&self.in_1, None);
-        print_ingredient(&self.in_2, None);
-        print_ingredient(&self.in_3, None);
-        print_ingredient(&self.in_4, None);
-        println!("\n  -- OUT  --");
-        print_ingredient(&self.out_1, None);
-        print_ingredient(&self.out_2, None);
-        println!("\n  -- CALC --");
-
-        if use_belt {
-            println!("Max belt use: {:8}", max_belt);
+fn calc(
+    out: &mut dyn Write,
+    cfg: &OutputConfig,
+    mut state: State,
+    all_recipes: RecipeMap,
+    recipes: &[String],
+    find: FindOpts,
+    opts: &CalcOpts,
+) -> Result<(), anyhow::Error> {
+    state.somersloops = opts.sloops;
+    let find = FindOpts { search_unlocks: opts.search_unlocks, ..find };
+    if cfg.format == Format::Json {
+        return calc_json(out, &state, &all_recipes, recipes, find, opts.all);
+    }
+    let mut printed = Vec::new();
+    for recipe in recipes {
+        if opts.oneline {
+            // Keep going on errors, so one bad query doesn't hide the rest
+            match matching(&all_recipes, recipe, find, opts.all) {
+                Ok(matches) => for r in matches {
+                    match calc_oneline(out, cfg, &state, r) {
+                        Ok(()) => printed.push(r),
+                        Err(e) => eprintln!("{recipe}: {e}"),
+                    }
+                },
+                Err(e) => eprintln!("{recipe}: {e}"),
+            }
+            continue;
         }
-        if use_pipe {
-            println!("Max pipe use: {:8}", max_pipe);
+        let matches = matching(&all_recipes, recipe, find, opts.all)?;
+        if matches.len() > 1 {
+            let names: Vec<&str> = matches.iter().map(|r| r.name.as_str()).collect();
+            writeln!(out, "\n{} recipes match {recipe}: {}", matches.len(), names.join(", "))?;
         }
-        if use_belt {
-            println!(
-                "Num of {} per belt: {:8.4}",
-                &self.building,
-                m_per_belt,
-            );
+        for r in matches {
+            if opts.raw {
+                writeln!(out, "{:#?}", r)?;
+                // Works without a prefered multiple or power usage, unlike calc
+                if let Some(m) = r.machines_per_belt(&state) {
+                    writeln!(out, "Num of {} per belt: {:.p$}", r.building, m, p = cfg.prec(4))?;
+                }
+                if let Some(m) = r.machines_per_pipe(&state) {
+                    writeln!(out, "Num of {} per pipe: {:.p$}", r.building, m, p = cfg.prec(4))?;
+                }
+                continue;
+            }
+            r.print_calc(out, cfg, &state)?;
+            if opts.outputs_detail {
+                r.print_outputs_detail(out, cfg, &state)?;
+            }
+            if opts.layout {
+                r.print_layout(out, &state)?;
+            }
+            if opts.chain {
+                r.print_input_producers(out, cfg, &state, &all_recipes)?;
+            }
+            if opts.explain_transport {
+                r.print_transport_explanation(out)?;
+            }
+            print_packaging_hints(out, &all_recipes, r)?;
+            printed.push(r);
         }
-        if use_pipe {
-            println!(
-                "Num of {} per pipe: {:8.4}",
-                &self.building,
-                m_per_pipe,
-            );
+    }
+    if opts.transport_summary {
+        let mut belts = 0.0;
+        let mut pipes = 0.0;
+        for r in printed {
+            let (b, p) = r.transport_needed(&state)?;
+            belts += b;
+            pipes += p;
         }
+        writeln!(out, "\nTotal: {} belts and {} pipes at {} / {} per minute", belts, pipes, state.belt_ipm, state.pipe_ipm)?;
+    }
+    Ok(())
+}
+
+/// Prints an array with each matching recipe and its blueprint calculation
+fn calc_json(out: &mut dyn Write, state: &State, all_recipes: &RecipeMap, recipes: &[String], find: FindOpts, all: bool) -> Result<(), anyhow::Error> {
+    let mut values = Vec::new();
+    for recipe in recipes {
+        for r in matching(all_recipes, recipe, find, all)? {
+            let calc = r.calc(state)?;
+            values.push(format!("{{\"recipe\":{},\"calc\":{}}}", r.to_json(), calc.to_json()));
+        }
+    }
+    writeln!(out, "{}", json::array(values.into_iter()))?;
+    Ok(())
+}
+
+/// The best match for `recipe`, or with `all` every match about as good
+fn matching<'a>(all_recipes: &'a RecipeMap, recipe: &str, find: FindOpts, all: bool) -> Result<Vec<&'a Recipe>, SatisError> {
+    if all {
+        find_recipes(all_recipes, recipe, find)
+    } else {
+        Ok(vec![find_recipe(all_recipes, recipe, find)?])
+    }
+}
+
+fn calc_oneline(out: &mut dyn Write, cfg: &OutputConfig, state: &State, r: &Recipe) -> Result<(), anyhow::Error> {
+    let RecipeCalc { n_boxes, clock, power_usage_mw, below_min_clock, .. } = r.calc(state)?;
+    let below = if below_min_clock { " (below minimum clock)" } else { "" };
+    writeln!(out, "{}: {} boxes @ {:.p$}%{below}, {:.p$} MW", r.name, n_boxes, clock * 100.0, power_usage_mw, p = cfg.prec(2))?;
+    Ok(())
+}
+
+fn power_total(
+    out: &mut dyn Write,
+    cfg: &OutputConfig,
+    state: &State,
+    all_recipes: &RecipeMap,
+    recipes: &[String],
+    find: FindOpts,
+) -> Result<(), anyhow::Error> {
+    let mut used = 0.0;
+    let mut generated = 0.0;
+    let mut values = Vec::new();
+    for recipe in recipes {
+        let r = find_recipe(all_recipes, recipe, find)?;
+        let RecipeCalc { power_usage_mw, .. } = r.calc(state)?;
+        if power_usage_mw < 0.0 {
+            generated -= power_usage_mw;
+        } else {
+            used += power_usage_mw;
+        }
+        match cfg.format {
+            Format::Text => writeln!(out, "{:40} {:10.p$} MW", r.name, power_usage_mw, p = cfg.prec(2))?,
+            Format::Json => values.push(format!("{{\"recipe\":{},\"power_usage_mw\":{}}}", json::string(&r.name), json::number(power_usage_mw))),
+        }
+    }
+    if cfg.format == Format::Json {
+        writeln!(
+            out,
+            "{{\"recipes\":{},\"power_use_mw\":{},\"power_output_mw\":{}}}",
+            json::array(values.into_iter()),
+            json::number(used),
+            json::number(generated),
+        )?;
+        return Ok(());
+    }
+    writeln!(out, "\n{:40} {:10.p$} MW", "Power use", used, p = cfg.prec(2))?;
+    if generated > 0.0 {
+        writeln!(out, "{:40} {:10.p$} MW", "Power output", generated, p = cfg.prec(2))?;
+        writeln!(out, "{:40} {:10.p$} MW", "Net power use", used - generated, p = cfg.prec(2))?;
+    }
+    Ok(())
+}
+
+fn compare(out: &mut dyn Write, cfg: &OutputConfig, state: &State, all_recipes: &RecipeMap, part: &str) -> Result<(), anyhow::Error> {
+    // Match the part name exactly, ignoring case
+    let part = all_recipes.values()
+        .flat_map(|r| r.outputs())
+        .find(|o| o.part.eq_ignore_ascii_case(part))
+        .map(|o| o.part.clone())
+        .with_context(|| format!("No recipe makes {part}"))?;
+    let mut rows: Vec<(&Recipe, &Ingredient, Option<f64>)> = ranked_producers(all_recipes, &part, state)
+        .into_iter()
+        .map(|r| {
+            let o = r.outputs().find(|o| o.part == part).expect("Producers make the part");
+            (r, o, r.base_power_mw())
+        })
+        .collect();
+    // Unknown power sorts last
+    let per_mw = |o: &Ingredient, power: Option<f64>| power.map_or(f64::NEG_INFINITY, |p| o.quantity / p);
+    rows.sort_by(|(_, a, a_power), (_, b, b_power)| per_mw(b, *b_power).total_cmp(&per_mw(a, *a_power)));
 
-        let print_parts = |modifier: f64| {
-            println!("Out:");
-            print_ingredient(&self.out_1, Some(modifier));
-            print_ingredient(&self.out_2, Some(modifier));
-            println!("In:");
-            print_ingredient(&self.in_1, Some(modifier));
-            print_ingredient(&self.in_2, Some(modifier));
-            print_ingredient(&self.in_3, Some(modifier));
-            print_ingredient(&self.in_4, Some(modifier));
+    if cfg.format == Format::Json {
+        let values = rows.iter().map(|(r, o, power)| format!(
+            "{{\"recipe\":{},\"output_per_min\":{},\"power_mw\":{}}}",
+            r.to_json(),
+            json::number(o.quantity),
+            power.map(json::number).unwrap_or_else(|| "null".into()),
+        ));
+        writeln!(out, "{}", json::array(values))?;
+        return Ok(());
+    }
+
+    let capacity = match rows.first().map(|(_, o, _)| o.transport()) {
+        Some(Transport::Pipe) => ("pipe", state.pipe_ipm),
+        _ => ("belt", state.belt_ipm),
+    };
+    writeln!(
+        out,
+        "{:32} {:14} {:>8} {:>9} {:>8} {:>8}  Inputs",
+        "Recipe", "Building", "Out/min", "Power MW", "Out/MW", format!("Per {}", capacity.0),
+    )?;
+    for (r, o, power) in rows {
+        let inputs: Vec<String> = r.inputs().map(|i| format!("{}x {}", i.quantity, i.part)).collect();
+        let name = if r.is_alt { format!("{} (alt)", r.name) } else { r.name.clone() };
+        writeln!(
+            out,
+            "{:32} {:14} {:8.p$} {:>9} {:>8} {:8.p$}  {}",
+            name,
+            r.building,
+            o.quantity,
+            power.map_or("?".to_string(), |p| format!("{:.p$}", p, p = cfg.prec(2))),
+            power.map_or("?".to_string(), |p| format!("{:.p$}", o.quantity / p, p = cfg.prec(2))),
+            capacity.1 / o.quantity,
+            inputs.join(" + "),
+            p = cfg.prec(2),
+        )?;
+    }
+    Ok(())
+}
+
+/// Prints the machines in `tree` and the summed materials to build them,
+/// leaving out buildings without a known cost
+fn build_cost(out: &mut dyn Write, cfg: &OutputConfig, tree: &TreeNode) -> Result<(), anyhow::Error> {
+    let buildings = tree.buildings();
+    let mut cost: Vec<Ingredient> = Vec::new();
+    for (building, count) in &buildings {
+        let Some(parts) = building_cost(building) else {
+            eprintln!("No build cost known for {building}, leaving out {count} of them");
+            continue;
         };
+        for part in parts {
+            let part = part.scale(*count as f64);
+            match cost.iter_mut().find(|i| **i == part) {
+                Some(i) => i.merge_with(&part),
+                None => cost.push(part),
+            }
+        }
+    }
+    cost.sort_by(|a, b| b.quantity.total_cmp(&a.quantity).then(a.part.cmp(&b.part)));
+    match cfg.format {
+        Format::Text => {
+            for (building, count) in &buildings {
+                writeln!(out, "{:24} {:10}", building, count)?;
+            }
+            writeln!(out)?;
+            for i in &cost {
+                writeln!(out, "{:24} {:10.p$}", i.part, i.quantity, p = cfg.prec(0))?;
+            }
+        },
+        Format::Json => writeln!(
+            out,
+            "{{\"buildings\":{},\"cost\":{}}}",
+            json::array(buildings.iter().map(|(b, n)| format!("{{\"building\":{},\"count\":{n}}}", json::string(b)))),
+            json::array(cost.iter().map(|i| i.to_json())),
+        )?,
+    }
+    Ok(())
+}
 
-        println!("\n  --  BP  --");
-        println!("{} [{:.0}]", self.name, n_boxes);
-        println!("Num {} per BP instance: {}", self.building, pref_mult);
-        println!("Clock: {:5.2} %", clock * 100.0);
-        println!("Power use: {:5.2} MW", power_usage_mw);
-        print_parts(clock * n_boxes * pref_mult);
-        if n_boxes > 1.0001 {
-            println!("\n{:>34}", "Per BP Instance");
-            print_parts(clock * pref_mult);
+fn points(out: &mut dyn Write, cfg: &OutputConfig, all_recipes: &RecipeMap, recipe: &str, find: FindOpts) -> Result<(), anyhow::Error> {
+    let r = find_recipe(all_recipes, recipe, find)?;
+    let mut total = 0.0;
+    let mut values = Vec::new();
+    for o in r.outputs() {
+        let points = sink_points(&o.part).map(|p| p as f64 * o.quantity);
+        total += points.unwrap_or(0.0);
+        match (cfg.format, points) {
+            (Format::Text, Some(p)) => writeln!(out, "{:24} {:10.p$} points/min", o.part, p, p = cfg.prec(0))?,
+            (Format::Text, None) => writeln!(out, "{:24} {:>10} (unsinkable or unknown)", o.part, "-")?,
+            (Format::Json, p) => values.push(format!(
+                "{{\"part\":{},\"points_per_min\":{}}}",
+                json::string(&o.part),
+                p.map(json::number).unwrap_or_else(|| "null".into()),
+            )),
         }
-        println!("\n{:>34}", format!("Per {}", self.building));
-        print_parts(clock);
+    }
+    match cfg.format {
+        Format::Text => writeln!(out, "{:24} {:10.p$} points/min per {}", "Total", total, r.building, p = cfg.prec(0))?,
+        Format::Json => writeln!(
+            out,
+            "{{\"recipe\":{},\"outputs\":{},\"total_per_min\":{}}}",
+            json::string(&r.name),
+            json::array(values.into_iter()),
+            json::number(total),
+        )?,
+    }
+    Ok(())
+}
 
-        Ok(())
+fn convert(out: &mut dyn Write, cfg: &OutputConfig, state: &State, amount: f64, from: &str, to: &str) -> Result<(), anyhow::Error> {
+    for unit in [from, to] {
+        if !RATE_UNITS.contains(&unit) {
+            bail!("Unknown unit {unit}, valid units are: {}", RATE_UNITS.join(", "));
+        }
     }
+    let converted = state.convert(amount, from, to).expect("Units checked above");
+    if cfg.format == Format::Json {
+        writeln!(
+            out,
+            "{{\"amount\":{},\"from\":{},\"converted\":{},\"to\":{}}}",
+            json::number(amount), json::string(from), json::number(converted), json::string(to),
+        )?;
+        return Ok(());
+    }
+    writeln!(out, "{} {} = {:.p$} {}", amount, from, converted, to, p = cfg.prec(4))?;
+    Ok(())
+}
+
+fn verify(out: &mut dyn Write, cfg: &OutputConfig, state: &State, all_recipes: &RecipeMap) -> Result<(), anyhow::Error> {
+    let mut buildings: Vec<&str> = all_recipes.values()
+        .map(|r| r.building.as_str())
+        .chain(BUILDINGS.iter().copied())
+        .collect();
+    buildings.sort();
+    buildings.dedup();
 
+    let yes_no = |b: bool| if b { "yes" } else { "NO" };
+    if cfg.format == Format::Text {
+        writeln!(
+            out,
+            "{:22} {:13} {:>8} {:>8} {:>9} {:>8}",
+            "Building", "Category", "Multiple", "Power", "Footprint", "Recipes",
+        )?;
+    }
+    let mut n_mismatches = 0;
+    let mut values = Vec::new();
+    for b in buildings {
+        let has_mult = state.prefered_building_multiple(b).is_some();
+        let has_power = base_power_usage_mw(b).is_some();
+        let has_footprint = building_footprint(b).is_some();
+        let category = BuildingCategory::of(b);
+        let n_recipes = all_recipes.values().filter(|r| r.building == b).count();
+        let ok = has_mult == has_power && has_power == has_footprint && BUILDINGS.contains(&b) && category.is_some();
+        if !ok { n_mismatches += 1; }
+        match cfg.format {
+            Format::Text => writeln!(
+                out,
+                "{:22} {:13} {:>8} {:>8} {:>9} {:>8}{}",
+                b,
+                category.map_or("NO".to_string(), |c| c.to_string()),
+                yes_no(has_mult),
+                yes_no(has_power),
+                yes_no(has_footprint),
+                n_recipes,
+                if ok { "" } else { "  <- mismatch" },
+            )?,
+            Format::Json => values.push(format!(
+                "{{\"building\":{},\"category\":{},\"has_multiple\":{},\"has_power\":{},\"has_footprint\":{},\"recipes\":{},\"ok\":{}}}",
+                json::string(b),
+                category.map_or("null".to_string(), |c| json::string(&c.to_string())),
+                has_mult,
+                has_power,
+                has_footprint,
+                n_recipes,
+                ok,
+            )),
+        }
+    }
+    match cfg.format {
+        Format::Text => writeln!(out, "\n{} buildings with mismatched tables", n_mismatches)?,
+        Format::Json => writeln!(
+            out,
+            "{{\"buildings\":{},\"mismatches\":{}}}",
+            json::array(values.into_iter()),
+            n_mismatches,
+        )?,
+    }
+    Ok(())
 }
 
-fn print_ingredient(i: &Option<Ingredient>, modify: Option<f64>) {
-    let i = match i {
-        Some(i) => i,
-        None => return,
-    };
-    let t = match i.transport() {
-        Transport::Belt => "Belt",
-        Transport::Pipe => "Pipe",
-    };
-    match modify {
-        None => println!("({:4})  {:27} {:15.4}", t, i.part, i.quantity),
-        Some(m) => println!("  {:24} {:7.2}", i.part, m * i.quantity),
+fn list(
+    out: &mut dyn Write,
+    cfg: &OutputConfig,
+    all_recipes: &RecipeMap,
+    pattern: &str,
+    opts: &ListOpts,
+) -> Result<(), anyhow::Error> {
+    let pattern: Vec<char> = pattern.to_lowercase().chars().collect();
+    let mut matches: Vec<&Recipe> = all_recipes.values()
+        .filter(|r| !opts.fluids || r.uses_pipe() || r.produces_pipe())
+        .filter(|r| opts.category.is_none() || r.building_category() == opts.category)
+        .filter(|r| !opts.no_alt || !r.is_alt)
+        .filter(|r| {
+            let name: Vec<char> = r.name.to_lowercase().chars().collect();
+            wildcard_match(&pattern, &name)
+        })
+        .collect();
+    if opts.count {
+        writeln!(out, "{}", matches.len())?;
+        return Ok(());
+    }
+    matches.sort_by(|a, b| a.name.cmp(&b.name));
+    if opts.json_lines {
+        print_json_lines(out, &matches)?;
+        return Ok(());
+    }
+    if cfg.format == Format::Json {
+        writeln!(out, "{}", json::array(matches.iter().map(|r| r.to_json())))?;
+        return Ok(());
+    }
+    for r in matches {
+        let locked = if r.is_unlocked { "" } else { " [LOCKED]" };
+        writeln!(out, "{}{}", r.summary_line(), locked)?;
+    }
+    Ok(())
+}
+
+fn print_json_lines(out: &mut dyn Write, recipes: &[&Recipe]) -> std::io::Result<()> {
+    for r in recipes {
+        writeln!(out, "{}", r.to_json())?;
+    }
+    Ok(())
+}
+
+/// Matches the whole of `text` against `pattern`, where `*` matches any
+/// number of characters and `?` matches exactly one.
+fn wildcard_match(pattern: &[char], text: &[char]) -> bool {
+    let (mut p, mut t) = (0, 0);
+    // Position of the last seen `*` in the pattern, and where in the text
+    // we were when we saw it, so we can backtrack and let it eat more.
+    let mut star: Option<(usize, usize)> = None;
+    while t < text.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == text[t]) {
+            p += 1;
+            t += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            star = Some((p, t));
+            p += 1;
+        } else if let Some((star_p, star_t)) = star {
+            p = star_p + 1;
+            t = star_t + 1;
+            star = Some((star_p, star_t + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Cli {
+        Cli::parse_from([&["satis"], args].concat())
+    }
+
+    fn config(state: State) -> Result<Config, SatisError> {
+        Ok(Config { state, extra_fluids: Vec::new() })
+    }
+
+    fn ingot(name: &str, is_alt: bool) -> (String, Recipe) {
+        let recipe = Recipe {
+            building: "Smelter".into(),
+            name: name.into(),
+            craft_time: 2.0,
+            is_alt,
+            unlocks: "0|0".into(),
+            is_unlocked: true,
+            in_1: Some(Ingredient { part: "Iron Ore".into(), quantity: 30.0 }),
+            in_2: None,
+            in_3: None,
+            in_4: None,
+            out_1: Some(Ingredient { part: "Iron Ingot".into(), quantity: 30.0 }),
+            out_2: None,
+            power_mw: None,
+        };
+        (name.into(), recipe)
+    }
+
+    #[test]
+    fn list_count_respects_filters() {
+        let all_recipes: RecipeMap = [
+            ingot("Iron Ingot", false),
+            ingot("Pure Iron Ingot", true),
+            ingot("Iron Alloy Ingot", true),
+            ingot("Copper Ingot", false),
+        ].into_iter().collect();
+        let count = |args: &[&str]| {
+            let Command::List { pattern, opts } = parse(args).command else { unreachable!() };
+            let mut out = Vec::new();
+            list(&mut out, &OutputConfig::default(), &all_recipes, &pattern, &opts).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+        assert_eq!(count(&["list", "*iron*", "--count"]), "3\n");
+        assert_eq!(count(&["list", "*iron*", "--count", "--no-alt"]), "1\n");
+        assert_eq!(count(&["list", "*", "--count", "--no-alt"]), "2\n");
+    }
+
+    #[test]
+    fn min_clock_above_max_clock_is_rejected() {
+        let cli = parse(&["--min-clock", "1.5", "--max-clock", "1.2", "list", "*"]);
+        let err = load_state(&cli, config(State::default())).unwrap_err();
+        assert_eq!(err.to_string(), "The min clock 1.5 is above the max clock 1.2");
+
+        // The flag is also checked against the config's max clock
+        let cli = parse(&["--min-clock", "1.5", "list", "*"]);
+        assert!(load_state(&cli, config(State::default())).is_err());
+
+        let cli = parse(&["--min-clock", "0.5", "--max-clock", "1.2", "list", "*"]);
+        let state = load_state(&cli, config(State::default())).unwrap();
+        assert_eq!((state.min_clock, state.max_clock), (0.5, 1.2));
     }
-    
 }
@@ -0,0 +1,421 @@
+use std::io::{self, Write};
+
+use crate::types::*;
+
+/// How reports are written
+#[derive(Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum Format {
+    /// Aligned text for reading
+    #[default]
+    Text,
+    /// A single JSON value, for other tools
+    Json,
+}
+
+/// Display options shared by all the printers
+#[derive(Default)]
+pub struct OutputConfig {
+    pub format: Format,
+    /// Also show per minute quantities per second
+    pub per_sec: bool,
+    /// Number of decimals to show, instead of each value's default
+    pub precision: Option<usize>,
+    /// Show quantities that are close to a simple fraction as one
+    pub fractions: bool,
+    /// Show a recipe's own quantities per craft instead of per minute
+    pub per_cycle: bool,
+}
+
+impl OutputConfig {
+    pub fn prec(&self, default: usize) -> usize {
+        self.precision.unwrap_or(default)
+    }
+}
+
+impl Recipe {
+    pub fn print_calc(&self, out: &mut dyn Write, cfg: &OutputConfig, state: &State) -> anyhow::Result<()> {
+        let RecipeCalc {
+            use_belt,
+            use_pipe,
+            m_per_belt,
+            m_per_pipe,
+            n_boxes,
+            pref_mult,
+            clock,
+            power_usage_mw,
+            transport_use,
+            amplification,
+            below_min_clock,
+        } = self.calc(state)?;
+        let amplified = self.amplified(amplification);
+        let (max_belt, max_pipe) = amplified.max_outputs();
+
+        writeln!(out, "\n{:12}{:>39}", self.building, self.name)?;
+        // Blueprint quantities further down stay per minute
+        let (shown, unit) = if cfg.per_cycle {
+            (amplified.scaled(1.0 / self.per_minute_factor()), format!("per {}s craft", self.craft_time))
+        } else {
+            (amplified.clone(), "per minute".to_string())
+        };
+        writeln!(out, "\n  --  IN  -- ({unit})")?;
+        print_ingredient(out, cfg, &shown.in_1, None)?;
+        print_ingredient(out, cfg, &shown.in_2, None)?;
+        print_ingredient(out, cfg, &shown.in_3, None)?;
+        print_ingredient(out, cfg, &shown.in_4, None)?;
+        writeln!(out, "\n  -- OUT  -- ({unit})")?;
+        print_ingredient(out, cfg, &shown.out_1, None)?;
+        print_ingredient(out, cfg, &shown.out_2, None)?;
+        writeln!(out, "\n  -- CALC --")?;
+
+        if state.somersloops > 0 {
+            writeln!(
+                out,
+                "Somersloops: {} per machine, output x{:.p$}, power x{:.p$}",
+                state.somersloops, amplification, amplification.powi(2), p = cfg.prec(2),
+            )?;
+        }
+        let (in_belts, in_pipes) = self.input_transport_counts();
+        writeln!(out, "Inputs: {} belts, {} pipes per machine", in_belts, in_pipes)?;
+
+        if use_belt {
+            writeln!(out, "Max belt use: {:8}", max_belt)?;
+        }
+        if use_pipe {
+            writeln!(out, "Max pipe use: {:8}", max_pipe)?;
+        }
+        if use_belt {
+            writeln!(
+                out,
+                "Num of {} per belt: {:8.p$}",
+                &self.building,
+                m_per_belt,
+                p = cfg.prec(4),
+            )?;
+        }
+        if use_pipe {
+            writeln!(
+                out,
+                "Num of {} per pipe: {:8.p$}",
+                &self.building,
+                m_per_pipe,
+                p = cfg.prec(4),
+            )?;
+        }
+        // Underclocked plans fit on one belt, but whole-only plans with a
+        // machine that outruns a slow belt or pipe need several
+        let full_clock_machines = n_boxes * pref_mult * clock;
+        if use_belt && full_clock_machines / m_per_belt > 1.0001 {
+            writeln!(out, "The blueprint needs {} belts of {}/min", (full_clock_machines / m_per_belt - 0.0001).ceil(), state.belt_ipm)?;
+        }
+        if use_pipe && full_clock_machines / m_per_pipe > 1.0001 {
+            writeln!(out, "The blueprint needs {} pipes of {}/min", (full_clock_machines / m_per_pipe - 0.0001).ceil(), state.pipe_ipm)?;
+        }
+
+        let print_parts = |out: &mut dyn Write, modifier: f64| -> io::Result<()> {
+            writeln!(out, "Out:")?;
+            print_ingredient(out, cfg, &amplified.out_1, Some(modifier))?;
+            print_ingredient(out, cfg, &amplified.out_2, Some(modifier))?;
+            writeln!(out, "In:")?;
+            print_ingredient(out, cfg, &self.in_1, Some(modifier))?;
+            print_ingredient(out, cfg, &self.in_2, Some(modifier))?;
+            print_ingredient(out, cfg, &self.in_3, Some(modifier))?;
+            print_ingredient(out, cfg, &self.in_4, Some(modifier))
+        };
+
+        writeln!(out, "\n  --  BP  --")?;
+        writeln!(out, "{} [{:.0}]", self.name, n_boxes)?;
+        writeln!(out, "Num {} per BP instance: {}", self.building, pref_mult)?;
+        writeln!(out, "Clock: {:5.p$} %", clock * 100.0, p = cfg.prec(2))?;
+        if below_min_clock {
+            writeln!(out, "Below the minimum clock of {:.p$} %", state.min_clock * 100.0, p = cfg.prec(2))?;
+        }
+        if power_shards(clock) > 0 {
+            writeln!(out, "Power shards per machine: {}", power_shards(clock))?;
+        }
+        if power_usage_mw < 0.0 {
+            writeln!(out, "Power output: {:5.p$} MW", -power_usage_mw, p = cfg.prec(2))?;
+        } else {
+            writeln!(out, "Power use: {:5.p$} MW", power_usage_mw, p = cfg.prec(2))?;
+        }
+        if state.whole_only {
+            writeln!(out, "Transport use: {:5.p$} %", transport_use * 100.0, p = cfg.prec(2))?;
+            if transport_use > 1.0001 {
+                writeln!(out, "Warning: a single {} at 100% overflows the belt or pipe", self.building)?;
+            }
+        }
+        print_parts(out, clock * n_boxes * pref_mult)?;
+        if n_boxes > 1.0001 {
+            writeln!(out, "\n{:>34}", "Per BP Instance")?;
+            print_parts(out, clock * pref_mult)?;
+        }
+        writeln!(out, "\n{:>34}", format!("Per {}", self.building))?;
+        print_parts(out, clock)?;
+
+        Ok(())
+    }
+
+    pub fn print_outputs_detail(&self, out: &mut dyn Write, cfg: &OutputConfig, state: &State) -> anyhow::Result<()> {
+        let RecipeCalc { n_boxes, pref_mult, clock, amplification, .. } = self.calc(state)?;
+        let modifier = clock * n_boxes * pref_mult;
+        let amplified = self.amplified(amplification);
+
+        writeln!(out, "\n  -- OUTPUTS --")?;
+        let mut belt_total = 0.0;
+        let mut n_belt_outputs = 0;
+        for o in amplified.outputs() {
+            let rate = o.quantity * modifier;
+            let (name, capacity) = match o.transport() {
+                Transport::Belt => {
+                    belt_total += rate;
+                    n_belt_outputs += 1;
+                    ("belts", state.belt_ipm)
+                },
+                Transport::Pipe => ("pipes", state.pipe_ipm),
+            };
+            writeln!(out, "  {:24} {:7.p$} ({} {})", o.part, rate, (rate / capacity).ceil(), name, p = cfg.prec(2))?;
+        }
+        if n_belt_outputs > 1 {
+            if belt_total <= state.belt_ipm {
+                writeln!(out, "Belt outputs can share one belt ({:.p$} / {})", belt_total, state.belt_ipm, p = cfg.prec(2))?;
+            } else {
+                writeln!(out, "Belt outputs need separate belts ({:.p$} > {})", belt_total, state.belt_ipm, p = cfg.prec(2))?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn print_layout(&self, out: &mut dyn Write, state: &State) -> anyhow::Result<()> {
+        let RecipeCalc { pref_mult, .. } = self.calc(state)?;
+        writeln!(out, "\n  -- LAYOUT --")?;
+        let footprint = match building_footprint(&self.building) {
+            Some(f) => f,
+            None => {
+                writeln!(out, "No footprint known for {}", self.building)?;
+                return Ok(());
+            },
+        };
+        let n_machines = pref_mult as u32;
+        match blueprint_layout(footprint, n_machines) {
+            Some((size, (cols, rows))) => writeln!(
+                out,
+                "{} {} ({}x{} m) fit a {}x{} blueprint as {} x {}",
+                n_machines, self.building, footprint.0, footprint.1, size, size, cols, rows,
+            )?,
+            None => writeln!(
+                out,
+                "{} {} ({}x{} m) do not fit any blueprint designer",
+                n_machines, self.building, footprint.0, footprint.1,
+            )?,
+        }
+        Ok(())
+    }
+
+    /// Prints the recipe picked to make each input, and how many of its
+    /// machines it takes to feed one machine of this recipe
+    pub fn print_input_producers(&self, out: &mut dyn Write, cfg: &OutputConfig, state: &State, all_recipes: &RecipeMap) -> io::Result<()> {
+        writeln!(out, "\n  -- PRODUCERS --")?;
+        for i in self.inputs() {
+            // Raw resources are mined, like in a Tree, even when a recipe
+            // such as unpackaging also makes them
+            let producer = if RAW_RESOURCES.contains(&i.part.as_str()) {
+                None
+            } else {
+                best_producer(all_recipes, &i.part, state)
+            };
+            let made = producer.and_then(|p| p.outputs().find(|o| o.part == i.part));
+            match (producer, made) {
+                (Some(p), Some(o)) => writeln!(
+                    out,
+                    "  {:24} {:7.p$} <- {:7.p$} x {} ({})",
+                    i.part, i.quantity, i.quantity / o.quantity, p.name, p.building, p = cfg.prec(2),
+                )?,
+                _ => writeln!(out, "  {:24} {:7.p$} <- raw resource", i.part, i.quantity, p = cfg.prec(2))?,
+            }
+        }
+        Ok(())
+    }
+
+    pub fn print_transport_explanation(&self, out: &mut dyn Write) -> io::Result<()> {
+        writeln!(out, "\n  -- TRANSPORT --")?;
+        for i in self.inputs().chain(self.outputs()) {
+            let reason = if i.is_fluid() {
+                "Pipe (in fluid list)"
+            } else {
+                "Belt (not in fluid list, default)"
+            };
+            writeln!(out, "  {:27} {}", i.part, reason)?;
+        }
+        Ok(())
+    }
+}
+
+/// Prints a production tree with each level indented further
+pub fn print_tree(out: &mut dyn Write, cfg: &OutputConfig, node: &TreeNode, depth: usize) -> io::Result<()> {
+    let indent = "  ".repeat(depth);
+    match node.recipe {
+        Some(r) => writeln!(
+            out,
+            "{indent}{}: {:.p$}/min <- {:.p$} x {} ({})",
+            node.part, node.rate, node.machines, r.name, r.building, p = cfg.prec(2),
+        )?,
+        None => writeln!(out, "{indent}{}: {:.p$}/min (raw)", node.part, node.rate, p = cfg.prec(2))?,
+    }
+    for i in &node.inputs {
+        print_tree(out, cfg, i, depth + 1)?;
+    }
+    Ok(())
+}
+
+/// Packaged fluids are separate parts from the bulk fluid, which is easy to
+/// mix up. Points to the Packager recipes linking the two.
+pub fn print_packaging_hints(out: &mut dyn Write, all_recipes: &RecipeMap, r: &Recipe) -> io::Result<()> {
+    if r.building == "Packager" { return Ok(()); }
+    let packagers: Vec<&Recipe> = all_recipes.values()
+        .filter(|p| p.building == "Packager")
+        .collect();
+    for i in r.inputs().chain(r.outputs()) {
+        // A packaged part is what a Packager makes out of a fluid
+        let bulk = packagers.iter()
+            .filter(|p| p.outputs().any(|o| o == i))
+            .find_map(|p| p.inputs().find(|pi| pi.is_fluid()));
+        let bulk = match bulk {
+            Some(b) => b,
+            None => continue,
+        };
+        let mut names: Vec<&str> = packagers.iter()
+            .filter(|p| p.inputs().chain(p.outputs()).any(|pi| pi == i))
+            .map(|p| p.name.as_str())
+            .collect();
+        names.sort();
+        writeln!(out, "\nNote: {} is packaged {}, see Packager recipes: {}", i.part, bulk.part, names.join(", "))?;
+    }
+    Ok(())
+}
+
+/// Prints the whole recipe dependency graph in Graphviz DOT format. Parts
+/// are ellipses and recipes are boxes, with edges from each input part to
+/// the recipe and from the recipe to each output part.
+pub fn print_recipe_graph(out: &mut dyn Write, all_recipes: &RecipeMap, unlocked_only: bool) -> io::Result<()> {
+    let mut recipes: Vec<&Recipe> = all_recipes.values()
+        .filter(|r| !unlocked_only || r.is_unlocked)
+        .collect();
+    recipes.sort_by(|a, b| a.name.cmp(&b.name));
+
+    let mut parts: Vec<&str> = recipes.iter()
+        .flat_map(|r| r.inputs().chain(r.outputs()))
+        .map(|i| i.part.as_str())
+        .collect();
+    parts.sort();
+    parts.dedup();
+
+    writeln!(out, "digraph recipes {{")?;
+    writeln!(out, "    rankdir=LR;")?;
+    for part in parts {
+        writeln!(out, "    \"part:{}\" [label=\"{}\", shape=ellipse];", dot_escape(part), dot_escape(part))?;
+    }
+    for r in recipes {
+        let name = dot_escape(&r.name);
+        writeln!(out, "    \"recipe:{}\" [label=\"{}\\n{}\", shape=box];", name, name, dot_escape(&r.building))?;
+        for i in r.inputs() {
+            writeln!(out, "    \"part:{}\" -> \"recipe:{}\";", dot_escape(&i.part), name)?;
+        }
+        for o in r.outputs() {
+            writeln!(out, "    \"recipe:{}\" -> \"part:{}\";", name, dot_escape(&o.part))?;
+        }
+    }
+    writeln!(out, "}}")
+}
+
+fn dot_escape(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+pub fn print_ingredient(out: &mut dyn Write, cfg: &OutputConfig, i: &Option<Ingredient>, modify: Option<f64>) -> io::Result<()> {
+    let i = match i {
+        Some(i) => i,
+        None => return Ok(()),
+    };
+    let t = match i.transport() {
+        Transport::Belt => "Belt",
+        Transport::Pipe => "Pipe",
+    };
+    let quantity = modify.unwrap_or(1.0) * i.quantity;
+    match (modify, fraction(quantity).filter(|_| cfg.fractions)) {
+        (None, Some(f)) => write!(out, "({:4})  {:27} {:>15}", t, i.part, f)?,
+        (None, None) => write!(out, "({:4})  {:27} {:15.p$}", t, i.part, i.quantity, p = cfg.prec(4))?,
+        (Some(_), Some(f)) => write!(out, "  {:24} {:>7}", i.part, f)?,
+        (Some(_), None) => write!(out, "  {:24} {:7.p$}", i.part, quantity, p = cfg.prec(2))?,
+    }
+    if cfg.per_sec {
+        write!(out, " ({:.p$}/s)", quantity / 60.0, p = cfg.prec(4))?;
+    }
+    writeln!(out)
+}
+
+/// Writes `x` as a whole number and a fraction with a denominator of at
+/// most 12, like "2/3" or "37 1/2", if it is within 0.001 of one. Whole
+/// numbers aren't fractions and return None.
+fn fraction(x: f64) -> Option<String> {
+    let whole = x.trunc();
+    let rest = (x - whole).abs();
+    if !(0.001..=0.999).contains(&rest) { return None; }
+    let den = (2..=12u32).find(|d| {
+        let num = rest * *d as f64;
+        (num - num.round()).abs() < 0.001 * *d as f64
+    })?;
+    let num = (rest * den as f64).round();
+    if whole == 0.0 {
+        let sign = if x < 0.0 { "-" } else { "" };
+        Some(format!("{sign}{num}/{den}"))
+    } else {
+        Some(format!("{whole} {num}/{den}"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn recipe(building: &str, name: &str, inputs: &[(&str, f64)], output: (&str, f64)) -> Recipe {
+        let ingredient = |&(part, quantity): &(&str, f64)| Some(Ingredient { part: part.into(), quantity });
+        Recipe {
+            building: building.into(),
+            name: name.into(),
+            craft_time: 6.0,
+            is_alt: false,
+            unlocks: String::new(),
+            is_unlocked: true,
+            in_1: inputs.first().and_then(ingredient),
+            in_2: inputs.get(1).and_then(ingredient),
+            in_3: None,
+            in_4: None,
+            out_1: ingredient(&output),
+            out_2: None,
+            power_mw: None,
+        }
+    }
+
+    #[test]
+    fn chain_treats_raw_resources_as_raw() {
+        let rubber = recipe("Refinery", "Rubber", &[("Crude Oil", 30.0), ("Packaged Water", 10.0)], ("Rubber", 20.0));
+        let all: RecipeMap = [
+            recipe("Packager", "Unpackage Oil", &[("Packaged Oil", 60.0)], ("Crude Oil", 60.0)),
+            recipe("Packager", "Packaged Water", &[("Water", 60.0), ("Empty Canister", 60.0)], ("Packaged Water", 60.0)),
+        ].into_iter().map(|r| (r.name.clone(), r)).collect();
+        let mut out = Vec::new();
+        rubber.print_input_producers(&mut out, &OutputConfig::default(), &State::default(), &all).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.contains("Crude Oil") && out.contains("<- raw resource"), "{out}");
+        assert!(!out.contains("Unpackage Oil"), "{out}");
+        assert!(out.contains("x Packaged Water (Packager)"), "{out}");
+    }
+
+    #[test]
+    fn fractions_are_recognised() {
+        assert_eq!(fraction(0.6667).as_deref(), Some("2/3"));
+        assert_eq!(fraction(37.5).as_deref(), Some("37 1/2"));
+        assert_eq!(fraction(-0.25).as_deref(), Some("-1/4"));
+        assert_eq!(fraction(3.0), None);
+        assert_eq!(fraction(0.0), None);
+    }
+}
@@ -0,0 +1,60 @@
+//! AWESOME Sink points per part. The recipe sheet has no point values, so
+//! these are from the game, and only cover the common parts.
+
+/// Points the AWESOME Sink gives for one `part`. None for fluids, which
+/// can't be sunk, and for parts missing from the table.
+pub fn sink_points(part: &str) -> Option<u32> {
+    match part {
+        "Bauxite" => Some(8),
+        "Caterium Ore" => Some(7),
+        "Coal" => Some(3),
+        "Copper Ore" => Some(3),
+        "Iron Ore" => Some(1),
+        "Limestone" => Some(2),
+        "Raw Quartz" => Some(15),
+        "Sulfur" => Some(11),
+        "Uranium" => Some(35),
+
+        "Caterium Ingot" => Some(42),
+        "Copper Ingot" => Some(6),
+        "Iron Ingot" => Some(2),
+        "Steel Ingot" => Some(8),
+
+        "Automated Wiring" => Some(1440),
+        "Cable" => Some(24),
+        "Circuit Board" => Some(696),
+        "Concrete" => Some(12),
+        "Copper Sheet" => Some(24),
+        "Encased Industrial Beam" => Some(528),
+        "Iron Plate" => Some(6),
+        "Iron Rod" => Some(4),
+        "Modular Frame" => Some(408),
+        "Motor" => Some(1520),
+        "Quartz Crystal" => Some(50),
+        "Quickwire" => Some(17),
+        "Reinforced Iron Plate" => Some(120),
+        "Rotor" => Some(140),
+        "Screw" => Some(2),
+        "Silica" => Some(20),
+        "Smart Plating" => Some(520),
+        "Stator" => Some(240),
+        "Steel Beam" => Some(64),
+        "Steel Pipe" => Some(24),
+        "Versatile Framework" => Some(1176),
+        "Wire" => Some(6),
+
+        "Empty Canister" => Some(60),
+        "Fabric" => Some(140),
+        "Petroleum Coke" => Some(20),
+        "Plastic" => Some(75),
+        "Polymer Resin" => Some(12),
+        "Rubber" => Some(60),
+
+        "Biomass" => Some(12),
+        "Leaves" => Some(3),
+        "Mycelia" => Some(10),
+        "Solid Biofuel" => Some(48),
+        "Wood" => Some(30),
+        _ => None,
+    }
+}
@@ -1,8 +1,12 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::hash::{Hash, Hasher};
+use std::path::Path;
+use std::sync::OnceLock;
 
-use anyhow::{anyhow, bail};
 use serde::{ Serialize, Deserialize };
 
+use crate::error::SatisError;
+
 #[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
 #[serde(tag = "type")]
 pub enum Transport {
@@ -10,7 +14,9 @@ pub enum Transport {
     Pipe,
 }
 
+/// Fields left out of a config file keep their default value
 #[derive(Debug, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
 pub struct State {
     pub belt_ipm: f64,
     pub pipe_ipm: f64,
@@ -21,13 +27,30 @@ pub struct State {
     pub pref_multiple_foundry: f64,
     pub pref_multiple_packager: f64,
     pub pref_multiple_blender: f64,
+    /// Lowest clock `Recipe::calc` should plan for. When the prefered number
+    /// of machines would need a lower clock, fewer machines per box are used
+    /// instead, and `RecipeCalc::below_min_clock` is set if that doesn't help.
+    pub min_clock: f64,
+    /// Highest clock `Recipe::calc` may plan for. Above 1.0 machines are
+    /// overclocked with power shards to need fewer of them.
+    pub max_clock: f64,
+    /// Only plan whole boxes of machines running at 100%, accepting that
+    /// the belts or pipes won't be saturated.
+    pub whole_only: bool,
+    /// Somersloops in each machine, see `Recipe::amplification`
+    pub somersloops: u32,
+    /// Pick alternate recipes over standard ones when choosing producers
+    pub prefer_alt: bool,
+    /// Names of recipes whose main product is `out_2`, with `out_1` being
+    /// the byproduct. See `Recipe::primary_output`.
+    pub out_2_primary: HashSet<String>,
 }
 
 impl Default for State {
     fn default() -> Self {
         Self {
-            belt_ipm: 780.0, // 480, ..
-            pipe_ipm: 600.0,
+            belt_ipm: BeltTier::default().ipm(),
+            pipe_ipm: PipeTier::default().ipm(),
             pref_multiple_assembler: 3.0,
             pref_multiple_blender: 4.0,
             pref_multiple_constructor: 3.0,
@@ -35,11 +58,94 @@ impl Default for State {
             pref_multiple_manufacturer: 2.0,
             pref_multiple_packager: 4.0,
             pref_multiple_refinery: 4.0,
+            min_clock: 0.0,
+            max_clock: 1.0,
+            whole_only: false,
+            somersloops: 0,
+            prefer_alt: false,
+            out_2_primary: HashSet::new(),
+        }
+    }
+}
+
+/// Conveyor belt marks, slowest first. The default is the one
+/// `State::default` plans for.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum BeltTier {
+    Mk1,
+    Mk2,
+    Mk3,
+    Mk4,
+    #[default]
+    Mk5,
+    Mk6,
+}
+
+impl BeltTier {
+    /// Items per minute the belt carries
+    pub fn ipm(self) -> f64 {
+        match self {
+            BeltTier::Mk1 => 60.0,
+            BeltTier::Mk2 => 120.0,
+            BeltTier::Mk3 => 270.0,
+            BeltTier::Mk4 => 480.0,
+            BeltTier::Mk5 => 780.0,
+            BeltTier::Mk6 => 1200.0,
         }
     }
 }
 
+/// Pipeline marks, slowest first. The default is the one `State::default`
+/// plans for.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum PipeTier {
+    Mk1,
+    #[default]
+    Mk2,
+}
+
+impl PipeTier {
+    /// Cubic meters per minute the pipe carries
+    pub fn ipm(self) -> f64 {
+        match self {
+            PipeTier::Mk1 => 300.0,
+            PipeTier::Mk2 => 600.0,
+        }
+    }
+}
+
+/// A TOML config file, holding `State` fields and an `extra_fluids` list
+pub struct Config {
+    pub state: State,
+    /// Parts to pass to `register_fluids`
+    pub extra_fluids: Vec<String>,
+}
+
+impl Config {
+    pub fn from_file(path: &Path) -> Result<Config, SatisError> {
+        let invalid = |reason: String| SatisError::InvalidConfig { path: path.display().to_string(), reason };
+        let config = std::fs::read_to_string(path).map_err(|e| invalid(e.to_string()))?;
+        let mut config: toml::Table = toml::from_str(&config).map_err(|e| invalid(e.to_string()))?;
+        // Fluids are global, not part of the State
+        let extra_fluids = match config.remove("extra_fluids") {
+            Some(fluids) => fluids.try_into().map_err(|e: toml::de::Error| invalid(e.to_string()))?,
+            None => Vec::new(),
+        };
+        let state = toml::Value::Table(config).try_into().map_err(|e: toml::de::Error| invalid(e.to_string()))?;
+        Ok(Config { state, extra_fluids })
+    }
+}
+
 impl State {
+    /// The default state, with belts and pipes of the given tiers
+    pub fn tier_preset(belt: BeltTier, pipe: PipeTier) -> State {
+        State {
+            belt_ipm: belt.ipm(),
+            pipe_ipm: pipe.ipm(),
+            ..State::default()
+        }
+    }
+
     pub fn prefered_building_multiple(&self, building: &str) -> Option<f64> {
         match building {
             "Constructor" => Some(self.pref_multiple_constructor),
@@ -54,13 +160,183 @@ impl State {
     }
 }
 
+/// Units that rates can be converted between with `State::convert`
+pub const RATE_UNITS: &[&str] = &["per-min", "per-sec", "belts", "pipes"];
+
+impl State {
+    /// Converts `amount` of one rate unit into another, using this state's
+    /// belt and pipe throughput. See `RATE_UNITS`.
+    pub fn convert(&self, amount: f64, from: &str, to: &str) -> Option<f64> {
+        let per_min = amount * self.unit_per_min(from)?;
+        Some(per_min / self.unit_per_min(to)?)
+    }
+
+    /// Items per minute in one of the given unit
+    fn unit_per_min(&self, unit: &str) -> Option<f64> {
+        match unit {
+            "per-min" => Some(1.0),
+            "per-sec" => Some(60.0),
+            "belts" => Some(self.belt_ipm),
+            "pipes" => Some(self.pipe_ipm),
+            _ => None,
+        }
+    }
+}
+
+/// Picks the recipe to make `part` with, out of all recipes producing it.
+/// See `ranked_producers` for the order.
+pub fn best_producer<'a>(all_recipes: &'a RecipeMap, part: &str, state: &State) -> Option<&'a Recipe> {
+    ranked_producers(all_recipes, part, state).into_iter().next()
+}
+
+/// All recipes producing `part`, best first. Recipes where `part` is the
+/// primary output win over ones making it as a byproduct, then standard
+/// recipes win over alternates unless `State::prefer_alt` is set, then by
+/// name.
+pub fn ranked_producers<'a>(all_recipes: &'a RecipeMap, part: &str, state: &State) -> Vec<&'a Recipe> {
+    let mut producers: Vec<&Recipe> = all_recipes.values()
+        .filter(|r| r.outputs().any(|o| o.part == part))
+        .collect();
+    producers.sort_by_key(|r| (
+        r.primary_output(state).map(|o| o.part != part).unwrap_or(true),
+        r.is_alt != state.prefer_alt,
+        &r.name,
+    ));
+    producers
+}
+
+/// A part in a production tree, and how it is made
+pub struct TreeNode<'a> {
+    pub part: String,
+    /// Items per minute needed
+    pub rate: f64,
+    /// None for raw resources
+    pub recipe: Option<&'a Recipe>,
+    /// Machines at 100% clock needed to make `rate`
+    pub machines: f64,
+    pub inputs: Vec<TreeNode<'a>>,
+}
+
+impl TreeNode<'_> {
+    /// The raw resources at the leaves of the tree, one per part, most
+    /// used first
+    pub fn raw_resources(&self) -> Vec<Ingredient> {
+        let mut raw: Vec<Ingredient> = Vec::new();
+        self.collect_raw(&mut raw);
+        raw.sort_by(|a, b| b.quantity.total_cmp(&a.quantity).then(a.part.cmp(&b.part)));
+        raw
+    }
+
+    /// Whole machines of each building in the tree at 100% clock, most
+    /// used first
+    pub fn buildings(&self) -> Vec<(String, u32)> {
+        let mut buildings: Vec<(String, u32)> = Vec::new();
+        self.collect_buildings(&mut buildings);
+        buildings.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        buildings
+    }
+
+    fn collect_buildings(&self, buildings: &mut Vec<(String, u32)>) {
+        if let Some(r) = self.recipe {
+            let n = (self.machines - 0.0001).ceil().max(1.0) as u32;
+            match buildings.iter_mut().find(|(b, _)| *b == r.building) {
+                Some((_, count)) => *count += n,
+                None => buildings.push((r.building.clone(), n)),
+            }
+        }
+        for i in &self.inputs {
+            i.collect_buildings(buildings);
+        }
+    }
+
+    fn collect_raw(&self, raw: &mut Vec<Ingredient>) {
+        if self.recipe.is_none() {
+            let leaf = Ingredient { part: self.part.clone(), quantity: self.rate };
+            match raw.iter_mut().find(|i| **i == leaf) {
+                Some(i) => i.merge_with(&leaf),
+                None => raw.push(leaf),
+            }
+        }
+        for i in &self.inputs {
+            i.collect_raw(raw);
+        }
+    }
+}
+
+/// Expands `r` into the tree of recipes making its inputs, down to raw
+/// resources, for `rate` per minute of its primary output. Each part is
+/// made with the best producer that doesn't lead back to a part further up
+/// the tree. Byproducts are ignored.
+pub fn production_tree<'a>(all_recipes: &'a RecipeMap, r: &'a Recipe, rate: f64, state: &State) -> Result<TreeNode<'a>, SatisError> {
+    let part = r.primary_output(state)
+        .ok_or_else(|| SatisError::MissingOutput(r.name.clone()))?
+        .part.clone();
+    let mut path = vec![part];
+    expand_recipe(all_recipes, r, rate, state, &mut path)
+}
+
+fn expand_recipe<'a>(
+    all_recipes: &'a RecipeMap,
+    r: &'a Recipe,
+    rate: f64,
+    state: &State,
+    path: &mut Vec<String>,
+) -> Result<TreeNode<'a>, SatisError> {
+    let part = path.last().expect("Path starts with the root part").clone();
+    let made = r.outputs()
+        .find(|o| o.part == part)
+        .expect("Only recipes producing the part are expanded");
+    let machines = rate / made.quantity;
+    let mut inputs = Vec::new();
+    for i in r.inputs() {
+        inputs.push(expand_part(all_recipes, &i.part, i.quantity * machines, state, path)?);
+    }
+    Ok(TreeNode { part, rate, recipe: Some(r), machines, inputs })
+}
+
+fn expand_part<'a>(
+    all_recipes: &'a RecipeMap,
+    part: &str,
+    rate: f64,
+    state: &State,
+    path: &mut Vec<String>,
+) -> Result<TreeNode<'a>, SatisError> {
+    if let Some(i) = path.iter().position(|p| p == part) {
+        let mut cycle = path[i..].to_vec();
+        cycle.push(part.into());
+        return Err(SatisError::ProductionCycle(cycle));
+    }
+    let producers = ranked_producers(all_recipes, part, state);
+    if RAW_RESOURCES.contains(&part) || producers.is_empty() {
+        return Ok(TreeNode { part: part.into(), rate, recipe: None, machines: 0.0, inputs: Vec::new() });
+    }
+    path.push(part.into());
+    // Falls back to the next producer when one leads into a cycle, like
+    // unpackaging a fluid that has to be packaged first
+    let mut first_cycle = None;
+    for r in producers {
+        match expand_recipe(all_recipes, r, rate, state, path) {
+            Ok(node) => {
+                path.pop();
+                return Ok(node);
+            },
+            Err(e @ SatisError::ProductionCycle(_)) => {
+                first_cycle.get_or_insert(e);
+            },
+            Err(e) => return Err(e),
+        }
+    }
+    path.pop();
+    Err(first_cycle.expect("There was at least one producer"))
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Recipes {
     pub recipes: RecipeMap,
 }
 pub type RecipeMap = HashMap<String, Recipe>;
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Recipe {
     pub building: String,
     pub name: String,
@@ -74,9 +350,146 @@ pub struct Recipe {
     pub in_4: Option<Ingredient>,
     pub out_1: Option<Ingredient>,
     pub out_2: Option<Ingredient>,
+    /// Average power use at 100% clock in MW, for buildings like the
+    /// Particle Accelerator whose power use depends on the recipe
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub power_mw: Option<f64>,
 }
 
 impl Recipe {
+    pub fn inputs(&self) -> impl Iterator<Item = &Ingredient> {
+        [&self.in_1, &self.in_2, &self.in_3, &self.in_4].into_iter().flatten()
+    }
+
+    pub fn outputs(&self) -> impl Iterator<Item = &Ingredient> {
+        [&self.out_1, &self.out_2].into_iter().flatten()
+    }
+
+    /// Returns a copy of the recipe with every input and output quantity
+    /// multiplied by `factor`
+    pub fn scaled(&self, factor: f64) -> Recipe {
+        let scale = |i: &Option<Ingredient>| i.as_ref().map(|i| i.scale(factor));
+        Recipe {
+            in_1: scale(&self.in_1),
+            in_2: scale(&self.in_2),
+            in_3: scale(&self.in_3),
+            in_4: scale(&self.in_4),
+            out_1: scale(&self.out_1),
+            out_2: scale(&self.out_2),
+            ..self.clone()
+        }
+    }
+
+    /// Output multiplier from `State::somersloops` in each machine. Filling
+    /// all of a building's slots doubles its output.
+    pub fn amplification(&self, state: &State) -> Result<f64, SatisError> {
+        if state.somersloops == 0 { return Ok(1.0); }
+        let slots = somersloop_slots(&self.building).unwrap_or(0);
+        if state.somersloops > slots {
+            return Err(SatisError::TooManySomersloops { building: self.building.clone(), slots });
+        }
+        Ok(1.0 + state.somersloops as f64 / slots as f64)
+    }
+
+    /// This recipe with outputs multiplied by `amplification`
+    pub fn amplified(&self, amplification: f64) -> Recipe {
+        let scale = |i: &Option<Ingredient>| i.as_ref().map(|i| i.scale(amplification));
+        Recipe {
+            out_1: scale(&self.out_1),
+            out_2: scale(&self.out_2),
+            ..self.clone()
+        }
+    }
+
+    /// Compact one line description for use in lists, with per minute
+    /// quantities, e.g. `Constructor | Iron Rod | 15x Iron Ingot -> 15x Iron Rod`
+    pub fn summary_line(&self) -> String {
+        let join = |ingredients: Vec<&Ingredient>| {
+            ingredients.iter()
+                .map(|i| format!("{}x {}", i.quantity, i.part))
+                .collect::<Vec<String>>()
+                .join(" + ")
+        };
+        format!(
+            "{} | {} | {} -> {}",
+            self.building,
+            self.name,
+            join(self.inputs().collect()),
+            join(self.outputs().collect()),
+        )
+    }
+
+    /// Number of crafting cycles per minute at 100% clock
+    pub fn per_minute_factor(&self) -> f64 {
+        60.0 / self.craft_time
+    }
+
+    /// Inputs consumed per crafting cycle, rather than per minute
+    pub fn input_per_cycle(&self) -> Vec<Ingredient> {
+        let f = 1.0 / self.per_minute_factor();
+        self.inputs().map(|i| i.scale(f)).collect()
+    }
+
+    /// Outputs produced per crafting cycle, rather than per minute
+    pub fn output_per_cycle(&self) -> Vec<Ingredient> {
+        let f = 1.0 / self.per_minute_factor();
+        self.outputs().map(|i| i.scale(f)).collect()
+    }
+
+    /// Whether any input needs a pipe
+    pub fn uses_pipe(&self) -> bool {
+        self.inputs().any(|i| i.transport() == Transport::Pipe)
+    }
+
+    /// Whether any output needs a pipe
+    pub fn produces_pipe(&self) -> bool {
+        self.outputs().any(|i| i.transport() == Transport::Pipe)
+    }
+
+    /// Number of distinct (belt, pipe) inputs a single machine needs fed
+    pub fn input_transport_counts(&self) -> (usize, usize) {
+        let n_pipes = self.inputs().filter(|i| i.transport() == Transport::Pipe).count();
+        (self.inputs().count() - n_pipes, n_pipes)
+    }
+
+    /// Number of (belts, pipes) needed to carry every input and output of
+    /// the blueprint suggested by `calc`
+    pub fn transport_needed(&self, state: &State) -> Result<(f64, f64), SatisError> {
+        let RecipeCalc { n_boxes, pref_mult, clock, amplification, .. } = self.calc(state)?;
+        let bp = self.amplified(amplification).scaled(clock * n_boxes * pref_mult);
+        let mut belts = 0.0;
+        let mut pipes = 0.0;
+        for i in bp.inputs().chain(bp.outputs()) {
+            match i.transport() {
+                Transport::Belt => belts += (i.quantity / state.belt_ipm).ceil(),
+                Transport::Pipe => pipes += (i.quantity / state.pipe_ipm).ceil(),
+            }
+        }
+        Ok((belts, pipes))
+    }
+
+    /// The output this recipe is made for, which is `out_1` unless the
+    /// state says otherwise
+    pub fn primary_output(&self, state: &State) -> Option<&Ingredient> {
+        if state.out_2_primary.contains(&self.name) {
+            self.out_2.as_ref()
+        } else {
+            self.out_1.as_ref()
+        }
+    }
+
+    /// Power use of one machine at 100% clock in MW, if known
+    pub fn base_power_mw(&self) -> Option<f64> {
+        self.power_mw.or_else(|| base_power_usage_mw(&self.building))
+    }
+
+    pub fn building_category(&self) -> Option<BuildingCategory> {
+        BuildingCategory::of(&self.building)
+    }
+
+    /// Returns the largest (belt, pipe) quantity of any single ingredient,
+    /// inputs and outputs alike. Sizing by these means every ingredient,
+    /// including a second output, fits on its own belt or pipe.
     pub fn max_outputs(&self) -> (f64, f64) {
         let mut belt = 0.0;
         let mut pipe = 0.0;
@@ -103,12 +516,48 @@ impl Recipe {
         (belt, pipe)
     }
 
-    pub fn calc(&self, state: &State) -> anyhow::Result<RecipeCalc> {
-        let (max_belt, max_pipe) = self.max_outputs();
-        let use_belt = max_belt >= 0.00001;
-        let use_pipe = max_pipe >= 0.00001;
-        let m_per_belt = state.belt_ipm / max_belt;
-        let m_per_pipe = state.pipe_ipm / max_pipe;
+    /// Number of machines at 100% that fill one belt with the largest belt
+    /// ingredient, or None if nothing goes by belt
+    pub fn machines_per_belt(&self, state: &State) -> Option<f64> {
+        let (max_belt, _) = self.max_outputs();
+        if max_belt < 0.00001 { return None; }
+        Some(state.belt_ipm / max_belt)
+    }
+
+    /// Same as `machines_per_belt`, for pipes
+    pub fn machines_per_pipe(&self, state: &State) -> Option<f64> {
+        let (_, max_pipe) = self.max_outputs();
+        if max_pipe < 0.00001 { return None; }
+        Some(state.pipe_ipm / max_pipe)
+    }
+
+    /// Fewest machines, all at the same clock no higher than
+    /// `State::max_clock`, that make exactly `target_out_rate` per minute
+    /// of the output `part`. Returns the number of machines and the clock.
+    pub fn buildings_for_rate(&self, state: &State, target_out_rate: f64, part: &str) -> Result<(u32, f64), SatisError> {
+        if target_out_rate <= 0.0 {
+            return Err(SatisError::InvalidQuantity(target_out_rate.to_string()));
+        }
+        let per_machine = self.outputs()
+            .find(|o| o.part == part)
+            .ok_or_else(|| SatisError::NotAnOutput { recipe: self.name.clone(), part: part.into() })?
+            .quantity * self.amplification(state)?;
+        // Allow for float error, so an exact fit doesn't get an extra machine
+        let n_machines = (target_out_rate / (per_machine * state.max_clock) - 0.0001).ceil().max(1.0);
+        let clock = target_out_rate / (n_machines * per_machine);
+        Ok((n_machines as u32, clock))
+    }
+
+    /// Suggests a blueprint that saturates the tightest belt or pipe. The
+    /// binding constraint is whichever of the belt and pipe limits allows
+    /// the fewest machines, see `max_outputs`.
+    pub fn calc(&self, state: &State) -> Result<RecipeCalc, SatisError> {
+        let amplification = self.amplification(state)?;
+        let amplified = self.amplified(amplification);
+        let use_belt = amplified.machines_per_belt(state).is_some();
+        let use_pipe = amplified.machines_per_pipe(state).is_some();
+        let m_per_belt = amplified.machines_per_belt(state).unwrap_or(f64::INFINITY);
+        let m_per_pipe = amplified.machines_per_pipe(state).unwrap_or(f64::INFINITY);
         let m_per_transport = if use_belt && use_pipe {
             m_per_belt.min(m_per_pipe)
         } else if use_belt {
@@ -117,17 +566,38 @@ impl Recipe {
             m_per_pipe
         };
 
-        let pref_mult = state.prefered_building_multiple(self.building.as_str()).ok_or(anyhow!("Please state a prefered number of machines for {}", &self.building))?;
-        let mut n_boxes = m_per_transport / pref_mult;
-        let mut clock = 1.0;
-        if n_boxes.fract().abs() > 0.0001 {
-            // need to +1 the amount of boxes and adjust clocks
-            let n_boxes_adjusted = n_boxes.ceil();
-            clock = n_boxes / n_boxes_adjusted;
-            n_boxes = n_boxes_adjusted;
+        let mut pref_mult = state.prefered_building_multiple(self.building.as_str()).ok_or_else(|| SatisError::NoPreferedMultiple(self.building.clone()))?;
+        let (mut n_boxes, mut clock) = if state.whole_only {
+            // Round down so we never overflow the belt, but build at least
+            // one. When a single box would overflow it, put fewer machines in
+            // each box. A single machine can still be too much for a slow
+            // belt, which shows as a transport use above 1.
+            pref_mult = pref_mult.min(m_per_transport.floor()).max(1.0);
+            ((m_per_transport / pref_mult).floor().max(1.0), 1.0)
+        } else {
+            fit_boxes(m_per_transport, pref_mult, state.max_clock)
+        };
+        // Whole only plans always run at 100%, so there is nothing to raise
+        if clock < state.min_clock && !state.whole_only {
+            // Fewer machines per box means less spare capacity to underclock
+            // away, try smaller multiples until the clock is high enough
+            let mut mult = pref_mult - 1.0;
+            while mult >= 1.0 {
+                let (n, c) = fit_boxes(m_per_transport, mult, state.max_clock);
+                if c >= state.min_clock {
+                    pref_mult = mult;
+                    n_boxes = n;
+                    clock = c;
+                    break;
+                }
+                mult -= 1.0;
+            }
         }
+        let below_min_clock = clock < state.min_clock;
 
-        let power_usage_mw = n_boxes * pref_mult * calc_power_usage_mw(self.building.as_str(), clock)?;
+        // Amplified machines draw power by the square of the amplification
+        let power_usage_mw = n_boxes * pref_mult * amplification.powi(2) * calc_power_usage_mw(self, clock)?;
+        let transport_use = n_boxes * pref_mult * clock / m_per_transport;
 
         Ok(RecipeCalc {
             use_belt,
@@ -138,10 +608,33 @@ impl Recipe {
             pref_mult,
             clock,
             power_usage_mw,
+            transport_use,
+            amplification,
+            below_min_clock,
         })
     }
 }
 
+/// Returns how many boxes of `pref_mult` machines are needed to run
+/// `n_machines` at 100%, and the clock they should run at, which is at most
+/// `max_clock`.
+fn fit_boxes(n_machines: f64, pref_mult: f64, max_clock: f64) -> (f64, f64) {
+    let mut n_boxes = n_machines / (pref_mult * max_clock);
+    let mut clock = max_clock;
+    if n_boxes.fract().abs() > 0.0001 {
+        // need to +1 the amount of boxes and adjust clocks
+        let n_boxes_adjusted = n_boxes.ceil();
+        clock = max_clock * n_boxes / n_boxes_adjusted;
+        n_boxes = n_boxes_adjusted;
+    }
+    (n_boxes, clock)
+}
+
+/// Power shards each machine needs to run at `clock`, one per 50% above 100%
+pub fn power_shards(clock: f64) -> u32 {
+    ((clock - 1.0) / 0.5 - 0.0001).ceil().max(0.0) as u32
+}
+
 pub struct RecipeCalc {
     pub use_belt: bool,
     pub use_pipe: bool,
@@ -151,50 +644,537 @@ pub struct RecipeCalc {
     pub pref_mult: f64,
     pub clock: f64,
     pub power_usage_mw: f64,
+    /// Fraction of the tightest belt or pipe that the blueprint uses
+    pub transport_use: f64,
+    /// Output multiplier from Somersloops, 1.0 without any
+    pub amplification: f64,
+    /// The clock is below `State::min_clock`, even with one machine per
+    /// box, or because `State::whole_only` keeps it at 100%
+    pub below_min_clock: bool,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+/// Equality and hashing only consider the part name, the quantity is
+/// ignored. This lets ingredients be collected into `HashSet`s of distinct
+/// parts.
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Ingredient {
     pub part: String,
     pub quantity: f64,
 }
 
+impl PartialEq for Ingredient {
+    fn eq(&self, other: &Self) -> bool {
+        self.part == other.part
+    }
+}
+
+impl Eq for Ingredient {}
+
+impl Hash for Ingredient {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.part.hash(state);
+    }
+}
+
 impl Ingredient {
+    pub fn scale(&self, factor: f64) -> Ingredient {
+        Ingredient {
+            part: self.part.clone(),
+            quantity: self.quantity * factor,
+        }
+    }
+
+    /// Adds the quantity of `other`, which must be the same part
+    pub fn merge_with(&mut self, other: &Ingredient) {
+        debug_assert_eq!(self.part, other.part);
+        self.quantity += other.quantity;
+    }
+
     pub fn transport(&self) -> Transport {
-        match self.part.as_str() {
-            "Alumina Solution" => Transport::Pipe,
-            "Fuel" => Transport::Pipe,
-            "Heavy Oil Residue" => Transport::Pipe,
-            "Ionised Fuel" => Transport::Pipe,
-            "Liquid Biofuel" => Transport::Pipe,
-            "Nitric Acid" => Transport::Pipe,
-            "Nitrogen Gas" => Transport::Pipe,
-            "Crude Oil" => Transport::Pipe,
-            "Rocket Fuel" => Transport::Pipe,
-            "Sulfuric Acid" => Transport::Pipe,
-            "Turbofuel" => Transport::Pipe,
-            "Water" => Transport::Pipe,
-            _ => Transport::Belt,
-        }
-    }
-}
-
-/// Returns the power usage in MW if possible
-fn calc_power_usage_mw(building: &str, clock: f64) -> anyhow::Result<f64> {
-    let base_power_usage = match building {
-        "Assembler" => 15.0,
-        "Blender" => 75.0,
-        "Constructor" => 4.0,
-        "Foundry" => 16.0,
-        "Manufacturer" => 55.0,
-        "Packager" => 10.0,
-        "Refinery" => 30.0,
-        "Smelter" => 4.0,
-        _ => bail!("Building {} has no defined base power usage.", building),
-    };
-
-    if clock <= 0.0 { bail!("Clock speed must no be less than 0"); }
-    if clock >= 2.5 { bail!("Clock speed must no be more than 2.5"); }
+        if self.is_fluid() {
+            Transport::Pipe
+        } else {
+            Transport::Belt
+        }
+    }
+
+    /// Whether the part is in the list of known fluids. Anything else is
+    /// assumed to go on a belt.
+    pub fn is_fluid(&self) -> bool {
+        FLUIDS.contains(&self.part.as_str())
+            || EXTRA_FLUIDS.get().is_some_and(|fluids| fluids.contains(&self.part))
+    }
+}
+
+/// Parts that are mined or extracted. Converter recipes can make some of
+/// them, but production trees stop at them.
+pub const RAW_RESOURCES: &[&str] = &[
+    "Bauxite",
+    "Caterium Ore",
+    "Coal",
+    "Copper Ore",
+    "Crude Oil",
+    "Iron Ore",
+    "Limestone",
+    "Nitrogen Gas",
+    "Raw Quartz",
+    "SAM",
+    "Sulfur",
+    "Uranium",
+    "Water",
+];
+
+/// Fluids added by `register_fluids`, on top of `FLUIDS`
+static EXTRA_FLUIDS: OnceLock<HashSet<String>> = OnceLock::new();
+
+/// Makes `Ingredient::is_fluid` treat these parts as fluids too, for fluids
+/// missing from `FLUIDS`, like ones from mods. The set is global and can
+/// only be set once, so call this at startup with every extra fluid,
+/// including a config's `Config::extra_fluids`. Returns false, changing
+/// nothing, if fluids were already registered.
+#[must_use]
+pub fn register_fluids(parts: &[String]) -> bool {
+    EXTRA_FLUIDS.set(parts.iter().cloned().collect()).is_ok()
+}
+
+/// Parts that need to be transported by pipe
+pub const FLUIDS: &[&str] = &[
+    "Alumina Solution",
+    "Fuel",
+    "Heavy Oil Residue",
+    "Ionised Fuel",
+    "Liquid Biofuel",
+    "Nitric Acid",
+    "Nitrogen Gas",
+    "Crude Oil",
+    "Rocket Fuel",
+    "Sulfuric Acid",
+    "Turbofuel",
+    "Water",
+];
+
+/// Returns the (width, length) of a building in meters
+pub fn building_footprint(building: &str) -> Option<(f64, f64)> {
+    match building {
+        "Assembler" => Some((10.0, 15.0)),
+        "Blender" => Some((18.0, 16.0)),
+        "Constructor" => Some((8.0, 10.0)),
+        "Converter" => Some((16.0, 16.0)),
+        "Foundry" => Some((10.0, 9.0)),
+        "Manufacturer" => Some((18.0, 20.0)),
+        "Packager" => Some((8.0, 8.0)),
+        "Particle Accelerator" => Some((24.0, 38.0)),
+        "Quantum Encoder" => Some((22.0, 48.0)),
+        "Refinery" => Some((10.0, 20.0)),
+        "Smelter" => Some((6.0, 9.0)),
+        _ => None,
+    }
+}
+
+/// Side of a foundation in meters
+pub const FOUNDATION_SIZE: f64 = 8.0;
+
+/// Side lengths, in foundations, of the Blueprint Designer Mk.1 through Mk.3
+pub const BLUEPRINT_SIZES: [u32; 3] = [4, 5, 6];
+
+/// Finds the smallest blueprint size that fits `n_machines` buildings of
+/// the given footprint in a grid, ignoring room for belts and pipes.
+/// Returns the blueprint size in foundations and the (columns, rows) of
+/// machines.
+pub fn blueprint_layout(footprint: (f64, f64), n_machines: u32) -> Option<(u32, (u32, u32))> {
+    let (w, l) = footprint;
+    for size in BLUEPRINT_SIZES {
+        let side = size as f64 * FOUNDATION_SIZE;
+        // Machines can be rotated, so try both orientations
+        for (w, l) in [(w, l), (l, w)] {
+            let max_cols = (side / w).floor() as u32;
+            let max_rows = (side / l).floor() as u32;
+            if max_cols == 0 || max_cols * max_rows < n_machines { continue; }
+            let cols = max_cols.min(n_machines);
+            let rows = n_machines.div_ceil(cols);
+            return Some((size, (cols, rows)));
+        }
+    }
+    None
+}
+
+/// Every building that any of the building tables knows about
+pub const BUILDINGS: &[&str] = &[
+    "Assembler",
+    "Blender",
+    "Constructor",
+    "Converter",
+    "Foundry",
+    "Manufacturer",
+    "Packager",
+    "Particle Accelerator",
+    "Quantum Encoder",
+    "Refinery",
+    "Smelter",
+];
+
+/// The broad kind of work a building does
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, clap::ValueEnum)]
+pub enum BuildingCategory {
+    Smelting,
+    Manufacturing,
+    Fluids,
+    Power,
+    Advanced,
+}
+
+impl BuildingCategory {
+    /// Returns the category of a building, if known
+    pub fn of(building: &str) -> Option<BuildingCategory> {
+        match building {
+            "Smelter" | "Foundry" => Some(BuildingCategory::Smelting),
+            "Constructor" | "Assembler" | "Manufacturer" => Some(BuildingCategory::Manufacturing),
+            "Refinery" | "Blender" | "Packager" => Some(BuildingCategory::Fluids),
+            "Biomass Burner" | "Coal Generator" | "Fuel Generator" | "Geothermal Generator" | "Nuclear Power Plant" => Some(BuildingCategory::Power),
+            "Converter" | "Particle Accelerator" | "Quantum Encoder" => Some(BuildingCategory::Advanced),
+            _ => None,
+        }
+    }
+}
+
+impl std::fmt::Display for BuildingCategory {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            BuildingCategory::Smelting => "Smelting",
+            BuildingCategory::Manufacturing => "Manufacturing",
+            BuildingCategory::Fluids => "Fluids",
+            BuildingCategory::Power => "Power",
+            BuildingCategory::Advanced => "Advanced",
+        };
+        f.pad(name)
+    }
+}
+
+/// Returns how many Somersloops fit in a building, if it takes any
+pub fn somersloop_slots(building: &str) -> Option<u32> {
+    match building {
+        "Constructor" | "Smelter" => Some(1),
+        "Assembler" | "Converter" | "Foundry" | "Refinery" => Some(2),
+        "Blender" | "Manufacturer" | "Particle Accelerator" | "Quantum Encoder" => Some(4),
+        _ => None,
+    }
+}
+
+/// Buildings whose power use depends on the recipe, see `Recipe::power_mw`
+pub const VARIABLE_POWER_BUILDINGS: &[&str] = &["Converter", "Particle Accelerator", "Quantum Encoder"];
+
+/// Returns the power usage at 100% clock in MW, if known
+pub fn base_power_usage_mw(building: &str) -> Option<f64> {
+    match building {
+        "Assembler" => Some(15.0),
+        "Blender" => Some(75.0),
+        "Constructor" => Some(4.0),
+        "Foundry" => Some(16.0),
+        "Manufacturer" => Some(55.0),
+        "Packager" => Some(10.0),
+        "Refinery" => Some(30.0),
+        "Smelter" => Some(4.0),
+        _ => None,
+    }
+}
+
+/// Returns the power usage in MW if possible. The recipe's own power use
+/// wins over the building's.
+fn calc_power_usage_mw(r: &Recipe, clock: f64) -> Result<f64, SatisError> {
+    let base_power_usage = r.base_power_mw()
+        .ok_or_else(|| SatisError::NoPowerUsage(r.building.clone()))?;
+
+    if clock <= 0.0 { return Err(SatisError::ClockTooLow); }
+    if clock > 2.5 + 0.0001 { return Err(SatisError::ClockTooHigh); }
 
     Ok(base_power_usage * clock.powf(1.321928))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ingredient(part: &str, quantity: f64) -> Option<Ingredient> {
+        Some(Ingredient { part: part.into(), quantity })
+    }
+
+    fn iron_rod() -> Recipe {
+        Recipe {
+            building: "Constructor".into(),
+            name: "Iron Rod".into(),
+            craft_time: 4.0,
+            is_alt: false,
+            unlocks: "0|2".into(),
+            is_unlocked: true,
+            in_1: ingredient("Iron Ingot", 15.0),
+            in_2: None,
+            in_3: None,
+            in_4: None,
+            out_1: ingredient("Iron Rod", 15.0),
+            out_2: None,
+            power_mw: None,
+        }
+    }
+
+    fn plastic() -> Recipe {
+        Recipe {
+            building: "Refinery".into(),
+            name: "Plastic".into(),
+            craft_time: 6.0,
+            in_1: ingredient("Crude Oil", 30.0),
+            out_1: ingredient("Plastic", 20.0),
+            out_2: ingredient("Heavy Oil Residue", 10.0),
+            ..iron_rod()
+        }
+    }
+
+    #[test]
+    fn refinery_uses_and_produces_pipes() {
+        assert!(plastic().uses_pipe());
+        assert!(plastic().produces_pipe());
+    }
+
+    #[test]
+    fn solid_recipe_uses_no_pipes() {
+        assert!(!iron_rod().uses_pipe());
+        assert!(!iron_rod().produces_pipe());
+    }
+
+    #[test]
+    fn scaled_multiplies_every_slot() {
+        let r = Recipe {
+            in_2: ingredient("Water", 5.0),
+            in_3: ingredient("Coal", 3.0),
+            in_4: ingredient("Sulfur", 1.0),
+            ..plastic()
+        };
+        let scaled = r.scaled(2.0);
+        let quantity = |i: &Option<Ingredient>| i.as_ref().map(|i| i.quantity);
+        assert_eq!(quantity(&scaled.in_1), Some(60.0));
+        assert_eq!(quantity(&scaled.in_2), Some(10.0));
+        assert_eq!(quantity(&scaled.in_3), Some(6.0));
+        assert_eq!(quantity(&scaled.in_4), Some(2.0));
+        assert_eq!(quantity(&scaled.out_1), Some(40.0));
+        assert_eq!(quantity(&scaled.out_2), Some(20.0));
+        assert_eq!(scaled.building, r.building);
+        assert_eq!(scaled.name, r.name);
+        assert_eq!(scaled.craft_time, r.craft_time);
+        assert_eq!(scaled.is_alt, r.is_alt);
+        assert_eq!(scaled.is_unlocked, r.is_unlocked);
+    }
+
+    fn recipe_map(recipes: Vec<Recipe>) -> RecipeMap {
+        recipes.into_iter().map(|r| (r.name.clone(), r)).collect()
+    }
+
+    #[test]
+    fn prefer_alt_picks_alt_producer() {
+        let steel_rod = Recipe {
+            name: "Steel Rod".into(),
+            is_alt: true,
+            in_1: ingredient("Steel Ingot", 12.0),
+            out_1: ingredient("Iron Rod", 48.0),
+            ..iron_rod()
+        };
+        let all = recipe_map(vec![iron_rod(), steel_rod]);
+        let best = |state: &State| ranked_producers(&all, "Iron Rod", state)[0].name.clone();
+        assert_eq!(best(&State::default()), "Iron Rod");
+        assert_eq!(best(&State { prefer_alt: true, ..State::default() }), "Steel Rod");
+    }
+
+    #[test]
+    fn every_building_has_its_category() {
+        use BuildingCategory::*;
+        let expected = [
+            ("Assembler", Manufacturing),
+            ("Blender", Fluids),
+            ("Constructor", Manufacturing),
+            ("Converter", Advanced),
+            ("Foundry", Smelting),
+            ("Manufacturer", Manufacturing),
+            ("Packager", Fluids),
+            ("Particle Accelerator", Advanced),
+            ("Quantum Encoder", Advanced),
+            ("Refinery", Fluids),
+            ("Smelter", Smelting),
+        ];
+        assert_eq!(BUILDINGS.len(), expected.len());
+        for (building, category) in expected {
+            assert!(BUILDINGS.contains(&building));
+            assert_eq!(BuildingCategory::of(building), Some(category), "{building}");
+        }
+        assert_eq!(BuildingCategory::of("Coal Generator"), Some(Power));
+        assert_eq!(BuildingCategory::of("Space Elevator"), None);
+    }
+
+    #[test]
+    fn config_reads_fluids_apart_from_state() {
+        let path = std::env::temp_dir().join(format!("satis-config-{}.toml", std::process::id()));
+        std::fs::write(&path, "belt_ipm = 480.0\nextra_fluids = [\"Liquid Biofuel\"]\n").unwrap();
+        let config = Config::from_file(&path);
+        std::fs::remove_file(&path).unwrap();
+        let config = config.unwrap();
+        assert_eq!(config.state.belt_ipm, 480.0);
+        assert_eq!(config.extra_fluids, ["Liquid Biofuel"]);
+    }
+
+    #[test]
+    fn tier_preset_speeds() {
+        let state = State::tier_preset(BeltTier::Mk3, PipeTier::Mk1);
+        assert_eq!(state.belt_ipm, 270.0);
+        assert_eq!(state.pipe_ipm, 300.0);
+        let state = State::tier_preset(BeltTier::Mk6, PipeTier::Mk2);
+        assert_eq!(state.belt_ipm, 1200.0);
+        assert_eq!(state.pipe_ipm, 600.0);
+        assert_eq!(state.pref_multiple_constructor, State::default().pref_multiple_constructor);
+    }
+
+    #[test]
+    fn primary_output_override_changes_producer() {
+        let resin = Recipe {
+            name: "Polymer Resin".into(),
+            out_1: ingredient("Polymer Resin", 130.0),
+            out_2: ingredient("Heavy Oil Residue", 20.0),
+            ..plastic()
+        };
+        let residue = Recipe {
+            name: "Heavy Oil Residue".into(),
+            out_1: ingredient("Heavy Oil Residue", 40.0),
+            out_2: ingredient("Polymer Resin", 20.0),
+            ..plastic()
+        };
+        let all = recipe_map(vec![resin, residue]);
+        let best = |state: &State| best_producer(&all, "Polymer Resin", state).map(|r| r.name.clone());
+        assert_eq!(best(&State::default()).as_deref(), Some("Polymer Resin"));
+        // Both now count Polymer Resin as primary, so the name decides
+        let state = State { out_2_primary: HashSet::from(["Heavy Oil Residue".to_string()]), ..State::default() };
+        assert_eq!(all["Heavy Oil Residue"].primary_output(&state).map(|o| o.part.as_str()), Some("Polymer Resin"));
+        assert_eq!(best(&state).as_deref(), Some("Heavy Oil Residue"));
+    }
+
+    #[test]
+    fn primary_output_override_beats_alt() {
+        let resin = Recipe {
+            name: "Polymer Resin".into(),
+            out_1: ingredient("Polymer Resin", 130.0),
+            out_2: ingredient("Heavy Oil Residue", 20.0),
+            ..plastic()
+        };
+        let residue = Recipe {
+            name: "Heavy Oil Residue".into(),
+            is_alt: true,
+            out_1: ingredient("Heavy Oil Residue", 40.0),
+            out_2: ingredient("Polymer Resin", 20.0),
+            ..plastic()
+        };
+        let all = recipe_map(vec![resin, residue]);
+        let best = |state: &State| best_producer(&all, "Heavy Oil Residue", state).map(|r| r.name.clone());
+        // The residue is only a byproduct of the standard recipe
+        assert_eq!(best(&State::default()).as_deref(), Some("Heavy Oil Residue"));
+        let state = State { out_2_primary: HashSet::from(["Polymer Resin".to_string()]), ..State::default() };
+        assert_eq!(best(&state).as_deref(), Some("Polymer Resin"));
+    }
+
+    #[test]
+    fn summary_line_format() {
+        assert_eq!(iron_rod().summary_line(), "Constructor | Iron Rod | 15x Iron Ingot -> 15x Iron Rod");
+    }
+
+    #[test]
+    fn per_cycle_quantities() {
+        // 15 per minute with a 4s craft
+        let r = iron_rod();
+        assert_eq!(r.input_per_cycle()[0].quantity, 1.0);
+        assert_eq!(r.output_per_cycle()[0].quantity, 1.0);
+    }
+
+    #[test]
+    fn machines_per_transport() {
+        let state = State { belt_ipm: 780.0, ..State::default() };
+        assert_eq!(iron_rod().machines_per_belt(&state), Some(52.0));
+        assert_eq!(iron_rod().machines_per_pipe(&state), None);
+        // 30 Crude Oil is the most piped, over 10 Heavy Oil Residue
+        assert_eq!(plastic().machines_per_pipe(&state), Some(20.0));
+    }
+
+    #[test]
+    fn second_output_can_bind_sizing() {
+        let r = Recipe {
+            name: "Polymer Resin".into(),
+            in_1: ingredient("Crude Oil", 30.0),
+            out_1: ingredient("Fuel", 20.0),
+            out_2: ingredient("Polymer Resin", 60.0),
+            ..plastic()
+        };
+        assert_eq!(r.max_outputs(), (60.0, 30.0));
+        let state = State::default();
+        // 13 machines fill the belt with Polymer Resin, but 20 would fill
+        // the pipes
+        let calc = r.calc(&state).unwrap();
+        assert_eq!(calc.m_per_belt, 13.0);
+        assert_eq!(calc.m_per_pipe, 20.0);
+        let machines = calc.n_boxes * calc.pref_mult * calc.clock;
+        assert!((machines * 60.0 - state.belt_ipm).abs() < 1e-9);
+        assert!((calc.transport_use - 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn low_min_clock_keeps_calc() {
+        let state = State { min_clock: 0.5, ..State::default() };
+        let with_floor = iron_rod().calc(&state).unwrap();
+        let without = iron_rod().calc(&State::default()).unwrap();
+        assert_eq!(with_floor.n_boxes, without.n_boxes);
+        assert_eq!(with_floor.pref_mult, without.pref_mult);
+        assert_eq!(with_floor.clock, without.clock);
+        assert!(!with_floor.below_min_clock);
+    }
+
+    #[test]
+    fn high_min_clock_lowers_pref_mult() {
+        // 52 rods per Mk5 belt need 18 boxes of 3 at 96.3%, or 26 boxes of 2
+        let state = State { min_clock: 0.99, ..State::default() };
+        let calc = iron_rod().calc(&state).unwrap();
+        assert_eq!(calc.pref_mult, 2.0);
+        assert_eq!(calc.n_boxes, 26.0);
+        assert!(calc.clock >= 0.99);
+        assert!(!calc.below_min_clock);
+    }
+
+    #[test]
+    fn whole_only_shrinks_boxes_for_slow_belts() {
+        let state = State { whole_only: true, belt_ipm: BeltTier::Mk1.ipm(), ..State::default() };
+        // 4 machines fill the belt, so one box of 3
+        let calc = iron_rod().calc(&state).unwrap();
+        assert_eq!((calc.n_boxes, calc.pref_mult, calc.clock), (1.0, 3.0, 1.0));
+        // 2 machines fill a 30/min belt, fewer than the prefered 3
+        let calc = iron_rod().calc(&State { belt_ipm: 30.0, ..state }).unwrap();
+        assert_eq!((calc.n_boxes, calc.pref_mult), (1.0, 2.0));
+        assert!(calc.transport_use <= 1.0);
+    }
+
+    #[test]
+    fn whole_only_reports_single_machine_overflow() {
+        let steel_screw = Recipe {
+            name: "Steel Screw".into(),
+            craft_time: 12.0,
+            is_alt: true,
+            in_1: ingredient("Steel Beam", 5.0),
+            out_1: ingredient("Screw", 260.0),
+            ..iron_rod()
+        };
+        let state = State { whole_only: true, belt_ipm: BeltTier::Mk1.ipm(), ..State::default() };
+        let calc = steel_screw.calc(&state).unwrap();
+        assert_eq!((calc.n_boxes, calc.pref_mult, calc.clock), (1.0, 1.0, 1.0));
+        assert!(calc.transport_use > 4.0);
+    }
+
+    #[test]
+    fn whole_only_runs_whole_boxes_at_full_clock() {
+        let state = State { whole_only: true, min_clock: 1.2, max_clock: 2.5, ..State::default() };
+        let calc = iron_rod().calc(&state).unwrap();
+        assert_eq!(calc.clock, 1.0);
+        assert_eq!(calc.n_boxes.fract(), 0.0);
+        assert_eq!(calc.n_boxes, 17.0);
+        assert!(calc.below_min_clock);
+    }
+}